@@ -1,10 +1,13 @@
 extern crate contour;
 extern crate clap;
 
-use contour::isoline_from_tiff;
-use clap::Clap;
+use contour::{
+    bytes_to_image, isoline_to_svg, ContourMode, ContourResult, IsolineOptions, Simplification,
+    Smoothing, Thresholds,
+};
+use clap::Parser;
 
-#[derive(Clap)]
+#[derive(Parser)]
 struct Opts {
     /// Input tiff file path
     #[clap(short, long)]
@@ -12,9 +15,103 @@ struct Opts {
     /// Output svg file path
     #[clap(short, long)]
     output: String,
-    /// Number of equally spaced isolines to draw
-    #[clap(short, long)]
-    num_lines: u32
+    /// Number of isolines to draw
+    #[clap(short, long, default_value = "10", conflicts_with_all = &["interval", "thresholds"])]
+    num_lines: usize,
+    /// How to place the --num-lines isolines: "equal" spacing, "nice" round numbers,
+    /// "quantile" for equal areas, "log" spacing or "jenks" natural breaks
+    #[clap(
+        long,
+        default_value = "equal",
+        possible_values = &["equal", "nice", "quantile", "log", "jenks"]
+    )]
+    scheme: String,
+    /// Draw an isoline every `interval` units, counting from --base
+    #[clap(long, conflicts_with = "thresholds")]
+    interval: Option<f64>,
+    /// Value that --interval counts from
+    #[clap(long, default_value = "0", requires = "interval")]
+    base: f64,
+    /// Comma separated list of the exact levels to draw
+    #[clap(long, use_value_delimiter = true, allow_hyphen_values = true)]
+    thresholds: Option<Vec<f32>>,
+    /// Trace isolines with marching "squares", or through "triangles" cut along a fixed
+    /// diagonal or along the diagonal the data suggests with "data-driven-triangles"
+    #[clap(
+        long,
+        default_value = "squares",
+        possible_values = &["squares", "triangles", "data-driven-triangles"]
+    )]
+    mode: String,
+    /// Contour the image upsampled this many times over with a Catmull-Rom spline,
+    /// for smoother lines through coarse grids
+    #[clap(long, default_value = "1")]
+    upsample: u32,
+    /// Drop points of the isolines that are within this distance, in pixels, of the
+    /// simplified line
+    #[clap(long)]
+    simplify: Option<f32>,
+    /// How --simplify thins out the isolines, with "visvalingam" the tolerance is the area
+    /// of the smallest triangle of neighbouring points to keep, in square pixels
+    #[clap(
+        long,
+        default_value = "douglas-peucker",
+        possible_values = &["douglas-peucker", "visvalingam"]
+    )]
+    simplify_method: String,
+    /// Simplify all the isolines together, keeping any point --simplify would drop if that
+    /// would make a line cross itself or another
+    #[clap(long, requires = "simplify")]
+    preserve_topology: bool,
+    /// Round off the isolines by cutting their corners with "chaikin", or draw them as
+    /// "catmull-rom" curves
+    #[clap(long, possible_values = &["chaikin", "catmull-rom"])]
+    smooth: Option<String>,
+    /// How many times --smooth chaikin cuts each corner, at most 8
+    #[clap(long, default_value = "2")]
+    iterations: u32,
+    /// How tightly --smooth catmull-rom curves bend, from 0 for the roundest to 1 for
+    /// straight lines
+    #[clap(long, default_value = "0")]
+    tension: f32,
+}
+
+impl Opts {
+    fn thresholds(&self) -> ContourResult<Thresholds> {
+        match (&self.thresholds, self.interval) {
+            (Some(thresholds), _) => Ok(Thresholds::Explicit(thresholds.clone())),
+            (None, Some(interval)) => Ok(Thresholds::Interval {
+                interval,
+                base: self.base,
+            }),
+            (None, None) => Thresholds::from_scheme(&self.scheme, self.num_lines),
+        }
+    }
+
+    fn isoline_options(&self) -> ContourResult<IsolineOptions> {
+        Ok(IsolineOptions {
+            mode: ContourMode::from_name(&self.mode)?,
+            upsample: self.upsample,
+            simplification: self
+                .simplify
+                .map(|tolerance| match self.simplify_method.as_str() {
+                    "visvalingam" => Simplification::VisvalingamWhyatt {
+                        min_area: tolerance,
+                    },
+                    _ => Simplification::DouglasPeucker { tolerance },
+                }),
+            preserve_topology: self.preserve_topology,
+            smoothing: match self.smooth.as_deref() {
+                Some("chaikin") => Some(Smoothing::Chaikin {
+                    iterations: self.iterations,
+                }),
+                Some(_) => Some(Smoothing::CatmullRom {
+                    tension: self.tension,
+                }),
+                None => None,
+            },
+        })
+    }
 }
 
 use std::fs;
@@ -24,10 +121,15 @@ use std::fs::File;
 fn main() {
     let opts: Opts = Opts::parse();
 
-    let img_bytes = fs::read(opts.input).expect("Issue reading input file");
-    let thresholds = (10..100).step_by(10).map(|x| x as f32).collect::<Vec<f32>>();
+    let img_bytes = fs::read(&opts.input).expect("Issue reading input file");
 
-    let svg = isoline_from_tiff(&img_bytes, &thresholds);
+    let svg = match bytes_to_image(&img_bytes).and_then(|image| {
+        let thresholds = opts.thresholds()?.levels(&image)?;
+        isoline_to_svg(&image, &thresholds, &opts.isoline_options()?)
+    }) {
+        Err(why) => panic!("couldn't contour {}: {}", opts.input, why),
+        Ok(svg) => svg,
+    };
 
     let svg_string = format!("{}", svg);
 
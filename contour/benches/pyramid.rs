@@ -0,0 +1,51 @@
+// Run with `cargo bench --features bench`
+use contour::{Image, MinMaxPyramid, TreeNode};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+// Rolling hills, so queries prune some blocks and descend into others
+fn terrain(size: u32) -> Image<f32> {
+    let data = (0..size * size)
+        .map(|i| {
+            let (x, y) = ((i % size) as f32, (i / size) as f32);
+            (x * 0.01).sin() * 100.0 + (y * 0.013).cos() * 80.0 + (x * y * 1e-5).sin() * 20.0
+        })
+        .collect();
+    Image::new(data, size, size)
+}
+
+fn build(c: &mut Criterion) {
+    let mut group = c.benchmark_group("build");
+    group.sample_size(10);
+
+    for size in &[512, 2048] {
+        let img = terrain(*size);
+        println!(
+            "{0}x{0}: TreeNode {1} bytes on the heap, MinMaxPyramid {2} bytes",
+            size,
+            TreeNode::create(&img).heap_size(),
+            MinMaxPyramid::create(&img).heap_size()
+        );
+
+        group.bench_with_input(BenchmarkId::new("create_node", size), &img, |b, img| {
+            b.iter(|| TreeNode::create(img))
+        });
+        group.bench_with_input(BenchmarkId::new("pyramid", size), &img, |b, img| {
+            b.iter(|| MinMaxPyramid::create(img))
+        });
+    }
+    group.finish();
+}
+
+fn crossing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("crossing");
+
+    let img = terrain(2048);
+    let tree = TreeNode::create(&img);
+    let pyramid = MinMaxPyramid::create(&img);
+    group.bench_function("create_node", |b| b.iter(|| tree.crossing(20.0)));
+    group.bench_function("pyramid", |b| b.iter(|| pyramid.crossing(20.0)));
+    group.finish();
+}
+
+criterion_group!(benches, build, crossing);
+criterion_main!(benches);
@@ -0,0 +1,97 @@
+use std::error::Error;
+use std::fmt;
+use tiff::TiffError;
+
+#[derive(Debug)]
+pub enum ContourError {
+    /// The TIFF could not be read
+    Decode(TiffError),
+    /// The number of samples doesn't match the image dimensions
+    DimensionMismatch {
+        width: u32,
+        height: u32,
+        samples: usize,
+    },
+    /// The TIFF stores its samples in a format that can't be contoured
+    UnsupportedSampleFormat(String),
+    /// The image or thresholds can't be contoured
+    InvalidData(String),
+}
+
+pub type ContourResult<T> = Result<T, ContourError>;
+
+impl fmt::Display for ContourError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContourError::Decode(err) => write!(f, "failed to decode TIFF: {}", err),
+            ContourError::DimensionMismatch {
+                width,
+                height,
+                samples,
+            } => write!(
+                f,
+                "expected {} samples for a {}x{} image but found {}",
+                *width as usize * *height as usize,
+                width,
+                height,
+                samples
+            ),
+            ContourError::UnsupportedSampleFormat(format) => {
+                write!(f, "unsupported sample format: {}", format)
+            }
+            ContourError::InvalidData(reason) => write!(f, "invalid data: {}", reason),
+        }
+    }
+}
+
+impl Error for ContourError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ContourError::Decode(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<TiffError> for ContourError {
+    fn from(err: TiffError) -> ContourError {
+        match err {
+            TiffError::UnsupportedError(unsupported) => {
+                ContourError::UnsupportedSampleFormat(unsupported.to_string())
+            }
+            err => ContourError::Decode(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tiff::TiffUnsupportedError;
+
+    #[test]
+    fn test_from_tiff_error() {
+        let err: ContourError = TiffError::LimitsExceeded.into();
+        assert!(matches!(
+            err,
+            ContourError::Decode(TiffError::LimitsExceeded)
+        ));
+
+        let err: ContourError =
+            TiffError::UnsupportedError(TiffUnsupportedError::UnsupportedBitsPerChannel(12)).into();
+        assert!(matches!(err, ContourError::UnsupportedSampleFormat(_)));
+    }
+
+    #[test]
+    fn test_display() {
+        let err = ContourError::DimensionMismatch {
+            width: 3,
+            height: 2,
+            samples: 5,
+        };
+        assert_eq!(
+            err.to_string(),
+            "expected 6 samples for a 3x2 image but found 5"
+        );
+    }
+}
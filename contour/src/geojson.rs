@@ -0,0 +1,215 @@
+use super::geotiff::GeoTransform;
+use super::marching_squares::{IsobandLayer, IsolineLayer, Path};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+pub type Position = [f64; 2];
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FeatureCollection {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub features: Vec<Feature>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Feature {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub geometry: Geometry,
+    pub properties: Properties,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "coordinates")]
+pub enum Geometry {
+    LineString(Vec<Position>),
+    MultiLineString(Vec<Vec<Position>>),
+    MultiPolygon(Vec<Vec<Vec<Position>>>),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Properties {
+    Isoline { threshold: f32 },
+    Isoband { lower: f32, upper: f32 },
+}
+
+impl FeatureCollection {
+    pub fn new(features: Vec<Feature>) -> FeatureCollection {
+        FeatureCollection {
+            kind: "FeatureCollection".to_string(),
+            features,
+        }
+    }
+}
+
+impl Feature {
+    pub fn new(geometry: Geometry, properties: Properties) -> Feature {
+        Feature {
+            kind: "Feature".to_string(),
+            geometry,
+            properties,
+        }
+    }
+}
+
+impl fmt::Display for FeatureCollection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let json = serde_json::to_string(self).map_err(|_| fmt::Error)?;
+        write!(f, "{}", json)
+    }
+}
+
+/// All the paths of an isoline as one feature, a LineString when there is only one path
+pub fn isoline_feature(isoline: &IsolineLayer, transform: Option<&GeoTransform>) -> Feature {
+    let mut lines: Vec<Vec<Position>> = isoline
+        .paths
+        .iter()
+        .map(|path| positions(path, transform))
+        .collect();
+
+    let geometry = if lines.len() == 1 {
+        Geometry::LineString(lines.remove(0))
+    } else {
+        Geometry::MultiLineString(lines)
+    };
+    Feature::new(
+        geometry,
+        Properties::Isoline {
+            threshold: isoline.threshold,
+        },
+    )
+}
+
+/// The polygons of an isoband as one MultiPolygon feature
+pub fn isoband_feature(isoband: &IsobandLayer, transform: Option<&GeoTransform>) -> Feature {
+    // Rings come out counter-clockwise in sample space, a mirroring transform turns them around
+    let reverse = transform.is_some_and(|transform| transform.flips_orientation());
+    let ring = |path: &Path| {
+        let mut ring = positions(path, transform);
+        if reverse {
+            ring.reverse();
+        }
+        ring
+    };
+
+    let polygons = isoband
+        .polygons()
+        .iter()
+        .map(|polygon| {
+            std::iter::once(&polygon.exterior)
+                .chain(&polygon.holes)
+                .map(ring)
+                .collect()
+        })
+        .collect();
+    Feature::new(
+        Geometry::MultiPolygon(polygons),
+        Properties::Isoband {
+            lower: isoband.lower,
+            upper: isoband.upper,
+        },
+    )
+}
+
+fn positions(path: &Path, transform: Option<&GeoTransform>) -> Vec<Position> {
+    path.points
+        .iter()
+        .map(|point| match transform {
+            Some(transform) => {
+                let world = transform.sample_to_world(point);
+                [world.x, world.y]
+            }
+            None => [point.x as f64, point.y as f64],
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::marching_squares::path;
+
+    #[test]
+    fn test_isoline_feature() {
+        let isoline = IsolineLayer {
+            threshold: 5.0,
+            paths: vec![path(&[(0.0, 0.5), (1.0, 0.5)], false)],
+        };
+        let feature = isoline_feature(&isoline, None);
+        assert_eq!(
+            feature.geometry,
+            Geometry::LineString(vec![[0.0, 0.5], [1.0, 0.5]])
+        );
+        assert_eq!(feature.properties, Properties::Isoline { threshold: 5.0 });
+
+        let isoline = IsolineLayer {
+            threshold: 5.0,
+            paths: vec![
+                path(&[(0.0, 0.5), (1.0, 0.5)], false),
+                path(&[(2.0, 0.5), (3.0, 0.5)], false),
+            ],
+        };
+        let transform = GeoTransform([100.0, 2.0, 0.0, 50.0, 0.0, -2.0]);
+        assert_eq!(
+            isoline_feature(&isoline, Some(&transform)).geometry,
+            Geometry::MultiLineString(vec![
+                vec![[101.0, 48.0], [103.0, 48.0]],
+                vec![[105.0, 48.0], [107.0, 48.0]],
+            ])
+        );
+    }
+
+    #[test]
+    fn test_isoband_feature() {
+        let square = path(
+            &[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0), (0.0, 0.0)],
+            true,
+        );
+        let isoband = IsobandLayer {
+            lower: 1.0,
+            upper: 2.0,
+            paths: vec![square],
+        };
+
+        let feature = isoband_feature(&isoband, None);
+        assert_eq!(
+            feature.geometry,
+            Geometry::MultiPolygon(vec![vec![vec![
+                [0.0, 0.0],
+                [1.0, 0.0],
+                [1.0, 1.0],
+                [0.0, 1.0],
+                [0.0, 0.0]
+            ]]])
+        );
+
+        // North up rasters flip the y axis, so the exterior is reversed to stay counter-clockwise
+        let transform = GeoTransform([0.0, 1.0, 0.0, 0.0, 0.0, -1.0]);
+        assert_eq!(
+            isoband_feature(&isoband, Some(&transform)).geometry,
+            Geometry::MultiPolygon(vec![vec![vec![
+                [0.5, -0.5],
+                [0.5, -1.5],
+                [1.5, -1.5],
+                [1.5, -0.5],
+                [0.5, -0.5]
+            ]]])
+        );
+    }
+
+    #[test]
+    fn test_serialize() {
+        let collection = FeatureCollection::new(vec![Feature::new(
+            Geometry::LineString(vec![[0.0, 0.5], [1.0, 0.5]]),
+            Properties::Isoline { threshold: 5.0 },
+        )]);
+        assert_eq!(
+            collection.to_string(),
+            "{\"type\":\"FeatureCollection\",\"features\":[{\"type\":\"Feature\",\
+             \"geometry\":{\"type\":\"LineString\",\"coordinates\":[[0.0,0.5],[1.0,0.5]]},\
+             \"properties\":{\"threshold\":5.0}}]}"
+        );
+    }
+}
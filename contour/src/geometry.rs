@@ -0,0 +1,137 @@
+use super::marching_squares::{IsolineLayer, Path};
+
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::prelude::*;
+
+/// Isolines packed into flat arrays, so they can be drawn to a canvas or uploaded to WebGL
+/// without building and parsing SVG strings
+///
+/// Coordinates are interleaved x, y pairs in the sample space of the image, where sample
+/// `(x, y)` sits at `(x, y)`. Unlike the SVG and GeoJSON output they stay there even for a
+/// georeferenced image, as that is what a canvas or WebGL draws in and an `f32` can't hold
+/// projected map coordinates to much better than a metre. `GeoTransform::sample_to_world`
+/// places a point on the map.
+///
+/// Path `i` covers the points from `offsets[i]` up to `offsets[i + 1]`, and closed paths
+/// repeat their first point at the end. `closed[i]` is 1 for a closed path and 0 otherwise.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ContourGeometry {
+    coords: Vec<f32>,
+    offsets: Vec<u32>,
+    closed: Vec<u32>,
+    threshold_indices: Vec<u32>,
+}
+
+impl ContourGeometry {
+    /// Paths of each layer tagged with the position of that layer in `layers`
+    pub fn from_isolines(layers: &[IsolineLayer]) -> ContourGeometry {
+        let mut geometry = ContourGeometry {
+            offsets: vec![0],
+            ..ContourGeometry::default()
+        };
+        for (index, layer) in layers.iter().enumerate() {
+            for path in &layer.paths {
+                geometry.push_path(path, index as u32);
+            }
+        }
+        geometry
+    }
+
+    fn push_path(&mut self, path: &Path, threshold_index: u32) {
+        self.coords
+            .extend(path.points.iter().flat_map(|point| [point.x, point.y]));
+        self.offsets.push((self.coords.len() / 2) as u32);
+        self.closed.push(path.circular as u32);
+        self.threshold_indices.push(threshold_index);
+    }
+
+    pub fn path_count(&self) -> usize {
+        self.closed.len()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ContourGeometry {
+    pub fn coords(&self) -> &[f32] {
+        &self.coords
+    }
+
+    pub fn offsets(&self) -> &[u32] {
+        &self.offsets
+    }
+
+    pub fn closed(&self) -> &[u32] {
+        &self.closed
+    }
+
+    pub fn threshold_indices(&self) -> &[u32] {
+        &self.threshold_indices
+    }
+}
+
+// Each getter copies its array into a new typed array on the JS side
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+impl ContourGeometry {
+    #[wasm_bindgen(getter)]
+    pub fn coords(&self) -> Vec<f32> {
+        self.coords.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn offsets(&self) -> Vec<u32> {
+        self.offsets.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn closed(&self) -> Vec<u32> {
+        self.closed.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn threshold_indices(&self) -> Vec<u32> {
+        self.threshold_indices.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::marching_squares::path;
+
+    #[test]
+    fn test_from_isolines() {
+        let layers = vec![
+            IsolineLayer {
+                threshold: 1.0,
+                paths: vec![path(&[(0.0, 0.5), (1.0, 0.5)], false)],
+            },
+            IsolineLayer {
+                threshold: 2.0,
+                paths: Vec::new(),
+            },
+            IsolineLayer {
+                threshold: 3.0,
+                paths: vec![path(
+                    &[(1.0, 0.5), (1.5, 1.0), (1.0, 1.5), (0.5, 1.0), (1.0, 0.5)],
+                    true,
+                )],
+            },
+        ];
+        let geometry = ContourGeometry::from_isolines(&layers);
+
+        assert_eq!(geometry.path_count(), 2);
+        assert_eq!(
+            geometry.coords(),
+            [0.0, 0.5, 1.0, 0.5, 1.0, 0.5, 1.5, 1.0, 1.0, 1.5, 0.5, 1.0, 1.0, 0.5]
+        );
+        assert_eq!(geometry.offsets(), [0, 2, 7]);
+        assert_eq!(geometry.closed(), [0, 1]);
+        assert_eq!(geometry.threshold_indices(), [0, 2]);
+
+        let empty = ContourGeometry::from_isolines(&[]);
+        assert_eq!(empty.path_count(), 0);
+        assert_eq!(empty.offsets(), [0]);
+    }
+}
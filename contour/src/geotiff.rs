@@ -0,0 +1,320 @@
+use super::util::*;
+use std::io::{Read, Seek};
+use tiff::decoder::Decoder;
+use tiff::tags::Tag;
+use tiff::TiffResult;
+
+// Not one of the tags the tiff crate knows by name
+const GDAL_METADATA: Tag = Tag::Unknown(42112);
+
+const GT_RASTER_TYPE_GEO_KEY: u16 = 1025;
+const RASTER_PIXEL_IS_POINT: u16 = 2;
+
+/// Affine transform from pixel/line to map coordinates, in the same order as GDAL
+///
+/// `x = t[0] + pixel * t[1] + line * t[2]`, `y = t[3] + pixel * t[4] + line * t[5]`,
+/// where pixel/line (0, 0) is the outer corner of the top left pixel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GeoTransform(pub [f64; 6]);
+
+impl GeoTransform {
+    pub fn apply(&self, pixel: f64, line: f64) -> Point<f64> {
+        let t = &self.0;
+        Point {
+            x: t[0] + pixel * t[1] + line * t[2],
+            y: t[3] + pixel * t[4] + line * t[5],
+        }
+    }
+
+    /// Map coordinates of a point in sample space, where each sample sits in the centre of its
+    /// pixel
+    pub fn sample_to_world(&self, point: &Point<f32>) -> Point<f64> {
+        self.apply(point.x as f64 + 0.5, point.y as f64 + 0.5)
+    }
+
+    /// Whether the transform mirrors the image, reversing the winding of every ring
+    pub fn flips_orientation(&self) -> bool {
+        let t = &self.0;
+        t[1] * t[5] - t[2] * t[4] < 0.0
+    }
+}
+
+/// Linear transform from stored sample values to physical units, `value * scale + offset`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScaleOffset {
+    pub scale: f64,
+    pub offset: f64,
+}
+
+impl Default for ScaleOffset {
+    fn default() -> ScaleOffset {
+        ScaleOffset {
+            scale: 1.0,
+            offset: 0.0,
+        }
+    }
+}
+
+impl ScaleOffset {
+    pub fn apply(&self, val: f32) -> f32 {
+        (val as f64 * self.scale + self.offset) as f32
+    }
+}
+
+/// The stored sample value marking missing data, from the GDAL_NODATA tag
+pub fn read_nodata<R: Read + Seek>(reader: &mut Decoder<R>) -> TiffResult<Option<f64>> {
+    match reader.find_tag(Tag::GdalNodata)? {
+        Some(value) => Ok(parse_nodata(&value.into_string()?)),
+        None => Ok(None),
+    }
+}
+
+/// Scale and offset of the first band, from the GDAL_METADATA tag
+pub fn read_scale_offset<R: Read + Seek>(reader: &mut Decoder<R>) -> TiffResult<ScaleOffset> {
+    match reader.find_tag(GDAL_METADATA)? {
+        Some(value) => Ok(parse_scale_offset(&value.into_string()?)),
+        None => Ok(ScaleOffset::default()),
+    }
+}
+
+/// Georeferencing from the GeoTIFF ModelTransformation, or ModelTiepoint and ModelPixelScale tags
+pub fn read_geotransform<R: Read + Seek>(
+    reader: &mut Decoder<R>,
+) -> TiffResult<Option<GeoTransform>> {
+    let mut read_f64_vec = |tag: Tag| -> TiffResult<Option<Vec<f64>>> {
+        reader
+            .find_tag(tag)?
+            .map(|value| value.into_f64_vec())
+            .transpose()
+    };
+    let transformation = read_f64_vec(Tag::ModelTransformationTag)?;
+    let tiepoints = read_f64_vec(Tag::ModelTiepointTag)?;
+    let pixel_scale = read_f64_vec(Tag::ModelPixelScaleTag)?;
+
+    let geo_keys = reader
+        .find_tag(Tag::GeoKeyDirectoryTag)?
+        .map(|value| value.into_u16_vec())
+        .transpose()?;
+    let pixel_is_point = geo_keys
+        .and_then(|keys| geo_key(&keys, GT_RASTER_TYPE_GEO_KEY))
+        .is_some_and(|raster_type| raster_type == RASTER_PIXEL_IS_POINT);
+
+    Ok(geotransform_from_tags(
+        transformation.as_deref(),
+        tiepoints.as_deref(),
+        pixel_scale.as_deref(),
+        pixel_is_point,
+    ))
+}
+
+fn geotransform_from_tags(
+    transformation: Option<&[f64]>,
+    tiepoints: Option<&[f64]>,
+    pixel_scale: Option<&[f64]>,
+    pixel_is_point: bool,
+) -> Option<GeoTransform> {
+    // Both forms map raster space to model space, in the top two rows of a 4x4 matrix
+    let raster_to_model = match (transformation, tiepoints, pixel_scale) {
+        (Some(m), _, _) if m.len() >= 8 => [m[3], m[0], m[1], m[7], m[4], m[5]],
+        (None, Some(tie), Some(scale)) if tie.len() >= 6 && scale.len() >= 2 => {
+            let (i, j, x, y) = (tie[0], tie[1], tie[3], tie[4]);
+            [
+                x - i * scale[0],
+                scale[0],
+                0.0,
+                y + j * scale[1],
+                0.0,
+                -scale[1],
+            ]
+        }
+        _ => return None,
+    };
+
+    // With PixelIsPoint raster space is anchored on the centre of the top left pixel rather
+    // than its corner, shift it by half a pixel to match GDAL
+    let t = raster_to_model;
+    if pixel_is_point {
+        Some(GeoTransform([
+            t[0] - 0.5 * t[1] - 0.5 * t[2],
+            t[1],
+            t[2],
+            t[3] - 0.5 * t[4] - 0.5 * t[5],
+            t[4],
+            t[5],
+        ]))
+    } else {
+        Some(GeoTransform(t))
+    }
+}
+
+// The key directory is a header of four shorts followed by
+// (key id, tag location, count, value) entries, with the value stored inline when the location is 0
+fn geo_key(keys: &[u16], key_id: u16) -> Option<u16> {
+    keys.get(4..)?
+        .chunks_exact(4)
+        .find(|entry| entry[0] == key_id && entry[1] == 0)
+        .map(|entry| entry[3])
+}
+
+fn parse_nodata(nodata: &str) -> Option<f64> {
+    nodata.trim().parse::<f64>().ok()
+}
+
+// GDAL_METADATA holds a small XML document along the lines of
+//   <GDALMetadata>
+//     <Item name="SCALE" sample="0" role="scale">0.1</Item>
+//     <Item name="OFFSET" sample="0" role="offset">-10</Item>
+//   </GDALMetadata>
+fn parse_scale_offset(metadata: &str) -> ScaleOffset {
+    let mut scale_offset = ScaleOffset::default();
+
+    let mut rest = metadata;
+    while let Some(start) = rest.find("<Item") {
+        rest = &rest[start..];
+        let (attributes, content) = match (rest.find('>'), rest.find("</Item>")) {
+            (Some(tag_end), Some(item_end)) if tag_end < item_end => {
+                (&rest[..tag_end], &rest[tag_end + 1..item_end])
+            }
+            _ => break,
+        };
+        rest = &rest[attributes.len()..];
+
+        // Only the first band is ever contoured
+        if attribute(attributes, "sample").is_some_and(|sample| sample != "0") {
+            continue;
+        }
+
+        let role = attribute(attributes, "role").or_else(|| attribute(attributes, "name"));
+        let value = content.trim().parse::<f64>();
+        match (role.map(|role| role.to_lowercase()), value) {
+            (Some(role), Ok(value)) if role == "scale" => scale_offset.scale = value,
+            (Some(role), Ok(value)) if role == "offset" => scale_offset.offset = value,
+            _ => {}
+        }
+    }
+
+    scale_offset
+}
+
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let pattern = format!(" {}=\"", name);
+    let start = tag.find(&pattern)? + pattern.len();
+    let length = tag[start..].find('"')?;
+    Some(&tag[start..start + length])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_nodata() {
+        assert_eq!(parse_nodata("-32768"), Some(-32768.0));
+        assert_eq!(parse_nodata(" -9999.5 "), Some(-9999.5));
+        assert!(parse_nodata("nan").unwrap().is_nan());
+        assert_eq!(parse_nodata(""), None);
+    }
+
+    #[test]
+    fn test_parse_scale_offset() {
+        let metadata = "<GDALMetadata>\n  \
+            <Item name=\"OFFSET\" sample=\"0\" role=\"offset\">-10</Item>\n  \
+            <Item name=\"SCALE\" sample=\"0\" role=\"scale\">0.1</Item>\n  \
+            <Item name=\"SCALE\" sample=\"1\" role=\"scale\">5</Item>\n  \
+            <Item name=\"AREA_OR_POINT\">Area</Item>\n\
+            </GDALMetadata>";
+
+        assert_eq!(
+            parse_scale_offset(metadata),
+            ScaleOffset {
+                scale: 0.1,
+                offset: -10.0
+            }
+        );
+        assert_eq!(parse_scale_offset(""), ScaleOffset::default());
+        assert_eq!(
+            parse_scale_offset("<Item role=\"scale\">2"),
+            ScaleOffset::default()
+        );
+    }
+
+    #[test]
+    fn test_geotransform_from_tags() {
+        let tiepoints = [0.0, 0.0, 0.0, 500000.0, 5000000.0, 0.0];
+        let pixel_scale = [30.0, 30.0, 0.0];
+
+        let area = geotransform_from_tags(None, Some(&tiepoints), Some(&pixel_scale), false);
+        assert_eq!(
+            area,
+            Some(GeoTransform([500000.0, 30.0, 0.0, 5000000.0, 0.0, -30.0]))
+        );
+
+        let point = geotransform_from_tags(None, Some(&tiepoints), Some(&pixel_scale), true);
+        assert_eq!(
+            point,
+            Some(GeoTransform([499985.0, 30.0, 0.0, 5000015.0, 0.0, -30.0]))
+        );
+
+        // A tiepoint away from the origin
+        let tiepoints = [10.0, 20.0, 0.0, 500300.0, 4999400.0, 0.0];
+        let offset = geotransform_from_tags(None, Some(&tiepoints), Some(&pixel_scale), false);
+        assert_eq!(offset, area);
+
+        #[rustfmt::skip]
+        let transformation = [
+            30.0, 5.0, 0.0, 500000.0,
+            -5.0, -30.0, 0.0, 5000000.0,
+            0.0, 0.0, 0.0, 0.0,
+            0.0, 0.0, 0.0, 1.0,
+        ];
+        let rotated = geotransform_from_tags(Some(&transformation), None, None, false);
+        assert_eq!(
+            rotated,
+            Some(GeoTransform([500000.0, 30.0, 5.0, 5000000.0, -5.0, -30.0]))
+        );
+
+        assert_eq!(
+            geotransform_from_tags(None, Some(&tiepoints), None, false),
+            None
+        );
+    }
+
+    #[test]
+    fn test_sample_to_world() {
+        let transform = GeoTransform([500000.0, 30.0, 0.0, 5000000.0, 0.0, -30.0]);
+        assert_eq!(
+            transform.sample_to_world(&Point { x: 0.0, y: 0.0 }),
+            Point {
+                x: 500015.0,
+                y: 4999985.0
+            }
+        );
+        assert_eq!(
+            transform.sample_to_world(&Point { x: 2.5, y: 1.0 }),
+            Point {
+                x: 500090.0,
+                y: 4999955.0
+            }
+        );
+        assert!(transform.flips_orientation());
+    }
+
+    #[test]
+    fn test_geo_key() {
+        let keys = [1, 1, 0, 2, 1024, 0, 1, 1, 1025, 0, 1, 2];
+        assert_eq!(geo_key(&keys, 1024), Some(1));
+        assert_eq!(geo_key(&keys, 1025), Some(2));
+        assert_eq!(geo_key(&keys, 3072), None);
+        assert_eq!(geo_key(&[], 1025), None);
+    }
+
+    #[test]
+    fn test_apply_scale_offset() {
+        let scale_offset = ScaleOffset {
+            scale: 0.5,
+            offset: 100.0,
+        };
+        assert_eq!(scale_offset.apply(10.0), 105.0);
+        assert_eq!(ScaleOffset::default().apply(1.25), 1.25);
+    }
+}
@@ -1,5 +1,20 @@
+mod error;
+mod geojson;
+mod geometry;
+mod geotiff;
 mod marching_squares;
+mod marching_triangles;
+mod polygon;
+mod pyramid;
+#[cfg(any(test, feature = "bench"))]
 mod quad_tree;
+mod simplify;
+mod smooth;
+mod thresholds;
+#[cfg(not(target_arch = "wasm32"))]
+mod tiled;
+mod topology;
+mod upsample;
 mod util;
 
 use std::fmt;
@@ -9,9 +24,26 @@ use wasm_bindgen::prelude::*;
 
 use serde::{Deserialize, Serialize};
 
-use marching_squares::{MarchingSquares, Path};
+pub use error::{ContourError, ContourResult};
+pub use geojson::{Feature, FeatureCollection, Geometry, Properties};
+pub use geometry::ContourGeometry;
+pub use geotiff::GeoTransform;
+pub use marching_squares::{IsobandLayer, IsolineLayer, MarchingSquares, Path, SaddleStrategy};
+pub use marching_triangles::{Diagonal, MarchingTriangles};
+pub use polygon::Polygon;
+pub use pyramid::MinMaxPyramid;
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub use quad_tree::TreeNode;
+pub use simplify::Simplification;
+pub use smooth::Smoothing;
 use std::io::Cursor;
+pub use thresholds::Thresholds;
 use tiff::decoder::*;
+#[cfg(not(target_arch = "wasm32"))]
+pub use tiled::{tiled_isolines_from_tiff, TiledIsolines};
+pub use topology::simplify_preserving_topology;
+pub use util::{Image, Point};
 
 #[cfg(not(target_arch = "wasm32"))]
 use rayon::prelude::*;
@@ -29,111 +61,756 @@ pub struct Svg {
     pub paths: Vec<SvgPath>,
 }
 
+/// How isolines are traced through the grid of samples
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ContourMode {
+    /// Marching squares, resolving saddle cells with the given strategy
+    Squares(SaddleStrategy),
+    /// Marching triangles, cutting each cell in two along the given diagonal
+    Triangles(Diagonal),
+}
+
+impl Default for ContourMode {
+    fn default() -> Self {
+        ContourMode::Squares(SaddleStrategy::default())
+    }
+}
+
+impl ContourMode {
+    /// "squares", "triangles" with a fixed diagonal or "data-driven-triangles"
+    pub fn from_name(name: &str) -> ContourResult<ContourMode> {
+        match name {
+            "squares" => Ok(ContourMode::default()),
+            "triangles" => Ok(ContourMode::Triangles(Diagonal::Fixed)),
+            "data-driven-triangles" => Ok(ContourMode::Triangles(Diagonal::DataDriven)),
+            _ => Err(ContourError::InvalidData(format!(
+                "unknown contour mode {}",
+                name
+            ))),
+        }
+    }
+
+    /// One layer per threshold, in the order given
+    pub fn isolines(self, img: &util::Image<f32>, thresholds: &[f32]) -> Vec<IsolineLayer> {
+        match self {
+            ContourMode::Squares(saddle) => MarchingSquares::new(img)
+                .with_saddle_strategy(saddle)
+                .isolines(thresholds),
+            ContourMode::Triangles(diagonal) => MarchingTriangles::new(img)
+                .with_diagonal(diagonal)
+                .isolines(thresholds),
+        }
+    }
+}
+
+/// How `isoline_to_svg` traces its isolines
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IsolineOptions {
+    pub mode: ContourMode,
+    /// Contour the image upsampled this many times over with a Catmull-Rom spline, for
+    /// smooth lines through coarse grids, 1 contours the samples as they are
+    pub upsample: u32,
+    /// Thin out the points of every line, after any upsampling
+    pub simplification: Option<Simplification>,
+    /// Simplify every level together, keeping any point whose removal would make a line
+    /// cross itself or another line
+    pub preserve_topology: bool,
+    /// Round off the corners of the lines as they're drawn, `isolines` leaves them as traced
+    pub smoothing: Option<Smoothing>,
+}
+
+impl Default for IsolineOptions {
+    fn default() -> Self {
+        IsolineOptions {
+            mode: ContourMode::default(),
+            upsample: 1,
+            simplification: None,
+            preserve_topology: false,
+            smoothing: None,
+        }
+    }
+}
+
+impl IsolineOptions {
+    /// One layer per threshold, in the order given, in the sample space of `img`
+    pub fn isolines(
+        &self,
+        img: &util::Image<f32>,
+        thresholds: &[f32],
+    ) -> ContourResult<Vec<IsolineLayer>> {
+        check_thresholds(thresholds)?;
+        let factor = match self.upsample {
+            0 => {
+                return Err(ContourError::InvalidData(
+                    "an image can't be upsampled 0 times over".to_string(),
+                ))
+            }
+            1 => return Ok(self.simplify(self.mode.isolines(img, thresholds))),
+            factor => factor,
+        };
+        // Samples are indexed with a u32, so the upsampled image has to fit one
+        let side = |length: u32| length.saturating_sub(1) as u64 * factor as u64 + 1;
+        if side(img.width) * side(img.height) > u32::MAX as u64 {
+            return Err(ContourError::InvalidData(format!(
+                "a {}x{} image is too large to upsample {} times over",
+                img.width, img.height, factor
+            )));
+        }
+
+        let mut layers = self
+            .mode
+            .isolines(&upsample::upsample(img, factor), thresholds);
+        for point in layers
+            .iter_mut()
+            .flat_map(|layer| &mut layer.paths)
+            .flat_map(|path| &mut path.points)
+        {
+            point.x /= factor as f32;
+            point.y /= factor as f32;
+        }
+        Ok(self.simplify(layers))
+    }
+
+    fn simplify(&self, layers: Vec<IsolineLayer>) -> Vec<IsolineLayer> {
+        let simplification = match &self.simplification {
+            Some(simplification) => simplification,
+            None => return layers,
+        };
+        if self.preserve_topology {
+            return topology::simplify_preserving_topology(&layers, simplification);
+        }
+        marching_squares::map_parallel(layers, |layer| IsolineLayer {
+            threshold: layer.threshold,
+            paths: layer
+                .paths
+                .iter()
+                .map(|path| simplification.apply(path))
+                .collect(),
+        })
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+pub fn isoline_from_tiff(
+    data: &[u8],
+    thresholds: &[f32],
+    simplify: Option<f32>,
+) -> Result<JsValue, JsValue> {
+    console_error_panic_hook::set_once();
+    to_js(
+        bytes_to_image(data)
+            .and_then(|image| isoline_to_svg(&image, thresholds, &simplified(simplify))),
+    )
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+pub fn isoline(
+    data: Vec<f32>,
+    width: u32,
+    height: u32,
+    thresholds: &[f32],
+    simplify: Option<f32>,
+) -> Result<JsValue, JsValue> {
+    console_error_panic_hook::set_once();
+    to_js(
+        data_to_image(data, width, height)
+            .and_then(|image| isoline_to_svg(&image, thresholds, &simplified(simplify))),
+    )
+}
+
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen]
-pub fn isoline_from_tiff(data: &[u8], thresholds: &[f32]) -> JsValue {
+pub fn isoband_from_tiff(data: &[u8], thresholds: &[f32]) -> Result<JsValue, JsValue> {
     console_error_panic_hook::set_once();
-    let image = bytes_to_image(data).unwrap();
-    let svg = isoline_to_svg(&image, thresholds).unwrap();
-    JsValue::from_serde(&svg).unwrap()
+    to_js(bytes_to_image(data).and_then(|image| isoband_to_svg(&image, thresholds)))
 }
 
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen]
-pub fn isoline(data: Vec<f32>, width: u32, height: u32, thresholds: &[f32]) -> JsValue {
+pub fn isoband(
+    data: Vec<f32>,
+    width: u32,
+    height: u32,
+    thresholds: &[f32],
+) -> Result<JsValue, JsValue> {
     console_error_panic_hook::set_once();
+    to_js(data_to_image(data, width, height).and_then(|image| isoband_to_svg(&image, thresholds)))
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+pub fn isoline_geojson_from_tiff(data: &[u8], thresholds: &[f32]) -> Result<JsValue, JsValue> {
+    console_error_panic_hook::set_once();
+    to_js(bytes_to_image(data).and_then(|image| isoline_to_geojson(&image, thresholds)))
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+pub fn isoband_geojson_from_tiff(data: &[u8], thresholds: &[f32]) -> Result<JsValue, JsValue> {
+    console_error_panic_hook::set_once();
+    to_js(bytes_to_image(data).and_then(|image| isoband_to_geojson(&image, thresholds)))
+}
+
+/// Isolines as typed arrays, see `ContourGeometry`
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+pub fn isoline_geometry_from_tiff(
+    data: &[u8],
+    thresholds: &[f32],
+    simplify: Option<f32>,
+) -> Result<ContourGeometry, JsValue> {
+    console_error_panic_hook::set_once();
+    bytes_to_image(data)
+        .and_then(|image| isoline_to_geometry(&image, thresholds, &simplified(simplify)))
+        .map_err(to_js_error)
+}
 
-    let image = util::Image::new(data, width, height);
-    let svg = isoline_to_svg(&image, thresholds).unwrap();
-    JsValue::from_serde(&svg).unwrap()
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+pub fn isoline_geometry(
+    data: Vec<f32>,
+    width: u32,
+    height: u32,
+    thresholds: &[f32],
+    simplify: Option<f32>,
+) -> Result<ContourGeometry, JsValue> {
+    console_error_panic_hook::set_once();
+    data_to_image(data, width, height)
+        .and_then(|image| isoline_to_geometry(&image, thresholds, &simplified(simplify)))
+        .map_err(to_js_error)
 }
 
-fn bytes_to_image(data: &[u8]) -> Result<util::Image<f32>, tiff::TiffError> {
+/// Isolines simplified with Douglas-Peucker when JS passes a tolerance, as they are otherwise
+#[cfg(target_arch = "wasm32")]
+fn simplified(tolerance: Option<f32>) -> IsolineOptions {
+    IsolineOptions {
+        simplification: tolerance.map(|tolerance| Simplification::DouglasPeucker { tolerance }),
+        ..IsolineOptions::default()
+    }
+}
+
+/// Levels picked from the image by one of the schemes named in `Thresholds::from_scheme`
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+pub fn thresholds_from_tiff(data: &[u8], scheme: &str, count: usize) -> Result<JsValue, JsValue> {
+    console_error_panic_hook::set_once();
+    to_js(
+        bytes_to_image(data)
+            .and_then(|image| Thresholds::from_scheme(scheme, count)?.levels(&image)),
+    )
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+pub fn thresholds(
+    data: Vec<f32>,
+    width: u32,
+    height: u32,
+    scheme: &str,
+    count: usize,
+) -> Result<JsValue, JsValue> {
+    console_error_panic_hook::set_once();
+    to_js(
+        data_to_image(data, width, height)
+            .and_then(|image| Thresholds::from_scheme(scheme, count)?.levels(&image)),
+    )
+}
+
+/// An image decoded once and kept with its min/max pyramid, so it can be contoured again at
+/// new thresholds without paying for either a second time
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub struct ContourGenerator {
+    marching_squares: MarchingSquares<'static>,
+}
+
+impl ContourGenerator {
+    pub fn from_image(img: util::Image<f32>) -> ContourGenerator {
+        ContourGenerator {
+            marching_squares: MarchingSquares::from_image(img),
+        }
+    }
+
+    fn isolines_svg(&self, thresholds: &[f32]) -> ContourResult<Svg> {
+        check_thresholds(thresholds)?;
+        let layers = self.marching_squares.isolines(thresholds);
+        Ok(isolines_svg(self.marching_squares.image(), &layers, None))
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+impl ContourGenerator {
+    pub fn from_tiff(data: &[u8]) -> Result<ContourGenerator, JsValue> {
+        console_error_panic_hook::set_once();
+        bytes_to_image(data)
+            .map(ContourGenerator::from_image)
+            .map_err(to_js_error)
+    }
+
+    #[wasm_bindgen(constructor)]
+    pub fn new(data: Vec<f32>, width: u32, height: u32) -> Result<ContourGenerator, JsValue> {
+        console_error_panic_hook::set_once();
+        data_to_image(data, width, height)
+            .map(ContourGenerator::from_image)
+            .map_err(to_js_error)
+    }
+
+    /// Resolve saddle cells in every later call with `saddle`
+    pub fn set_saddle_strategy(&mut self, saddle: SaddleStrategy) {
+        self.marching_squares.set_saddle_strategy(saddle);
+    }
+
+    pub fn isoline(&self, threshold: f32) -> Result<JsValue, JsValue> {
+        to_js(self.isolines_svg(&[threshold]))
+    }
+
+    pub fn isolines(&self, thresholds: &[f32]) -> Result<JsValue, JsValue> {
+        to_js(self.isolines_svg(thresholds))
+    }
+
+    pub fn isobands(&self, thresholds: &[f32]) -> Result<JsValue, JsValue> {
+        to_js(isobands_svg(&self.marching_squares, thresholds))
+    }
+
+    pub fn isoline_geometry(&self, thresholds: &[f32]) -> Result<ContourGeometry, JsValue> {
+        isolines_geometry(&self.marching_squares, thresholds).map_err(to_js_error)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ContourGenerator {
+    pub fn from_tiff(data: &[u8]) -> ContourResult<ContourGenerator> {
+        bytes_to_image(data).map(ContourGenerator::from_image)
+    }
+
+    pub fn new(data: Vec<f32>, width: u32, height: u32) -> ContourResult<ContourGenerator> {
+        data_to_image(data, width, height).map(ContourGenerator::from_image)
+    }
+
+    /// Resolve saddle cells in every later call with `saddle`
+    pub fn set_saddle_strategy(&mut self, saddle: SaddleStrategy) {
+        self.marching_squares.set_saddle_strategy(saddle);
+    }
+
+    pub fn isoline(&self, threshold: f32) -> ContourResult<Svg> {
+        self.isolines_svg(&[threshold])
+    }
+
+    pub fn isolines(&self, thresholds: &[f32]) -> ContourResult<Svg> {
+        self.isolines_svg(thresholds)
+    }
+
+    pub fn isobands(&self, thresholds: &[f32]) -> ContourResult<Svg> {
+        isobands_svg(&self.marching_squares, thresholds)
+    }
+
+    pub fn isoline_geometry(&self, thresholds: &[f32]) -> ContourResult<ContourGeometry> {
+        isolines_geometry(&self.marching_squares, thresholds)
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn to_js<T: Serialize>(result: ContourResult<T>) -> Result<JsValue, JsValue> {
+    let value = result.map_err(to_js_error)?;
+    JsValue::from_serde(&value).map_err(|err| js_sys::Error::new(&err.to_string()).into())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn to_js_error(err: ContourError) -> JsValue {
+    js_sys::Error::new(&err.to_string()).into()
+}
+
+fn data_to_image(data: Vec<f32>, width: u32, height: u32) -> ContourResult<util::Image<f32>> {
+    check_dimensions(width, height, data.len())?;
+    Ok(util::Image::new(data, width, height))
+}
+
+fn check_dimensions(width: u32, height: u32, samples: usize) -> ContourResult<()> {
+    if width == 0 || height == 0 {
+        return Err(ContourError::InvalidData(format!(
+            "a {}x{} image has nothing to contour",
+            width, height
+        )));
+    }
+    if samples != width as usize * height as usize {
+        return Err(ContourError::DimensionMismatch {
+            width,
+            height,
+            samples,
+        });
+    }
+    Ok(())
+}
+
+fn check_thresholds(thresholds: &[f32]) -> ContourResult<()> {
+    match thresholds.iter().find(|threshold| !threshold.is_finite()) {
+        Some(threshold) => Err(ContourError::InvalidData(format!(
+            "threshold {} is not a finite number",
+            threshold
+        ))),
+        None => Ok(()),
+    }
+}
+
+pub fn bytes_to_image(data: &[u8]) -> ContourResult<util::Image<f32>> {
     let mut reader = Decoder::new(Cursor::new(data))?;
-    let read_result = &reader.read_image()?;
+    let nodata = geotiff::read_nodata(&mut reader)?;
+    let scale_offset = geotiff::read_scale_offset(&mut reader)?;
+    let geotransform = geotiff::read_geotransform(&mut reader)?;
+
+    let image_data = decoded_to_f32(reader.read_image()?);
     let (width, height) = &reader.dimensions()?;
-    let image_data: Vec<f32> = match read_result {
+    // Images with several samples per pixel decode to a multiple of the expected length
+    check_dimensions(*width, *height, image_data.len())?;
+    let image_data = prepare_samples(image_data, nodata, &scale_offset);
+
+    let image = util::Image::new(image_data, *width, *height);
+    Ok(match geotransform {
+        Some(geotransform) => image.with_geotransform(geotransform),
+        None => image,
+    })
+}
+
+fn decoded_to_f32(result: DecodingResult) -> Vec<f32> {
+    match result {
         DecodingResult::U8(d) => d.iter().map(|x| *x as f32).collect(),
         DecodingResult::U16(d) => d.iter().map(|x| *x as f32).collect(),
         DecodingResult::U32(d) => d.iter().map(|x| *x as f32).collect(),
         DecodingResult::U64(d) => d.iter().map(|x| *x as f32).collect(),
-        DecodingResult::F32(d) => d.clone(),
+        DecodingResult::I8(d) => d.iter().map(|x| *x as f32).collect(),
+        DecodingResult::I16(d) => d.iter().map(|x| *x as f32).collect(),
+        DecodingResult::I32(d) => d.iter().map(|x| *x as f32).collect(),
+        DecodingResult::I64(d) => d.iter().map(|x| *x as f32).collect(),
+        DecodingResult::F32(d) => d,
         DecodingResult::F64(d) => d.iter().map(|x| *x as f32).collect(),
-    };
+    }
+}
 
-    Ok(util::Image::new(image_data, *width, *height))
+// Nodata is given in stored units, so mark missing samples as NaN before scaling the rest
+fn prepare_samples(
+    samples: Vec<f32>,
+    nodata: Option<f64>,
+    scale_offset: &geotiff::ScaleOffset,
+) -> Vec<f32> {
+    let nodata = nodata.map(|nodata| nodata as f32);
+    samples
+        .into_iter()
+        .map(|val| match nodata {
+            Some(nodata) if val == nodata => f32::NAN,
+            _ => scale_offset.apply(val),
+        })
+        .collect()
 }
 
 #[cfg(not(target_arch = "wasm32"))]
-pub fn isoline_from_tiff(data: &[u8], thresholds: &[f32]) -> Svg {
-    let img = bytes_to_image(data).unwrap();
-    isoline_to_svg(&img, thresholds).unwrap()
+pub fn isoline_from_tiff(data: &[u8], thresholds: &[f32]) -> ContourResult<Svg> {
+    let img = bytes_to_image(data)?;
+    isoline_to_svg(&img, thresholds, &IsolineOptions::default())
 }
 
 #[cfg(not(target_arch = "wasm32"))]
-pub fn isoline(data: Vec<f32>, width: u32, height: u32, thresholds: &[f32]) -> Svg {
-    let image = util::Image::new(data, width, height);
-    isoline_to_svg(&image, thresholds).unwrap()
+pub fn isoline_geojson_from_tiff(
+    data: &[u8],
+    thresholds: &[f32],
+) -> ContourResult<FeatureCollection> {
+    let img = bytes_to_image(data)?;
+    isoline_to_geojson(&img, thresholds)
 }
 
 #[cfg(not(target_arch = "wasm32"))]
-fn isoline_to_svg(img: &util::Image<f32>, thresholds: &[f32]) -> Result<Svg, tiff::TiffError> {
-    let marching_squares = MarchingSquares::new(img);
+pub fn isoband_geojson_from_tiff(
+    data: &[u8],
+    thresholds: &[f32],
+) -> ContourResult<FeatureCollection> {
+    let img = bytes_to_image(data)?;
+    isoband_to_geojson(&img, thresholds)
+}
 
-    let threshold_to_path = |(i, threshold): (usize, &f32)| {
-        let isoline = marching_squares.isoline(*threshold);
-        let path: String = isoline
-            .paths
-            .iter()
-            .map(|path| path_to_svg_path(path).join(" "))
-            .collect::<Vec<String>>()
-            .join(" ");
+#[cfg(not(target_arch = "wasm32"))]
+pub fn isoline(data: Vec<f32>, width: u32, height: u32, thresholds: &[f32]) -> ContourResult<Svg> {
+    let image = data_to_image(data, width, height)?;
+    isoline_to_svg(&image, thresholds, &IsolineOptions::default())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn isoband_from_tiff(data: &[u8], thresholds: &[f32]) -> ContourResult<Svg> {
+    let img = bytes_to_image(data)?;
+    isoband_to_svg(&img, thresholds)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn isoband(data: Vec<f32>, width: u32, height: u32, thresholds: &[f32]) -> ContourResult<Svg> {
+    let image = data_to_image(data, width, height)?;
+    isoband_to_svg(&image, thresholds)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn isoline_to_svg(
+    img: &util::Image<f32>,
+    thresholds: &[f32],
+    options: &IsolineOptions,
+) -> ContourResult<Svg> {
+    if let Some(smoothing) = &options.smoothing {
+        smoothing.check()?;
+    }
+    let layers = options.isolines(img, thresholds)?;
+    Ok(isolines_svg(img, &layers, options.smoothing))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn isolines_svg(
+    img: &util::Image<f32>,
+    layers: &[IsolineLayer],
+    smoothing: Option<Smoothing>,
+) -> Svg {
+    let isoline_to_path = |(i, isoline): (usize, &IsolineLayer)| {
+        let path = paths_to_svg_path(isoline.paths.iter(), img, smoothing);
+        SvgPath {
+            class: format!("threshold_{}_path", i),
+            fill: "none".to_string(),
+            path,
+        }
+    };
+
+    Svg {
+        view_box: svg_view_box(img),
+        paths: layers
+            .par_iter()
+            .enumerate()
+            .map(isoline_to_path)
+            .collect::<Vec<SvgPath>>(),
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn isoline_to_svg(
+    img: &util::Image<f32>,
+    thresholds: &[f32],
+    options: &IsolineOptions,
+) -> ContourResult<Svg> {
+    if let Some(smoothing) = &options.smoothing {
+        smoothing.check()?;
+    }
+    let layers = options.isolines(img, thresholds)?;
+    Ok(isolines_svg(img, &layers, options.smoothing))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn isolines_svg(
+    img: &util::Image<f32>,
+    layers: &[IsolineLayer],
+    smoothing: Option<Smoothing>,
+) -> Svg {
+    let isoline_to_path = |(i, isoline): (usize, &IsolineLayer)| {
+        let path = paths_to_svg_path(isoline.paths.iter(), img, smoothing);
         SvgPath {
             class: format!("threshold_{}_path", i),
             fill: "none".to_string(),
-            path: path,
+            path,
+        }
+    };
+
+    Svg {
+        view_box: svg_view_box(img),
+        paths: layers
+            .iter()
+            .enumerate()
+            .map(isoline_to_path)
+            .collect::<Vec<SvgPath>>(),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn isoline_to_geometry(
+    img: &util::Image<f32>,
+    thresholds: &[f32],
+    options: &IsolineOptions,
+) -> ContourResult<ContourGeometry> {
+    Ok(ContourGeometry::from_isolines(
+        &options.isolines(img, thresholds)?,
+    ))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn isoline_to_geometry(
+    img: &util::Image<f32>,
+    thresholds: &[f32],
+    options: &IsolineOptions,
+) -> ContourResult<ContourGeometry> {
+    Ok(ContourGeometry::from_isolines(
+        &options.isolines(img, thresholds)?,
+    ))
+}
+
+fn isolines_geometry(
+    marching_squares: &MarchingSquares,
+    thresholds: &[f32],
+) -> ContourResult<ContourGeometry> {
+    check_thresholds(thresholds)?;
+    Ok(ContourGeometry::from_isolines(
+        &marching_squares.isolines(thresholds),
+    ))
+}
+
+/// Consecutive pairs of sorted thresholds, each pair bounding one isoband
+fn band_bounds(thresholds: &[f32]) -> Vec<(f32, f32)> {
+    let mut sorted = thresholds.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    sorted.windows(2).map(|pair| (pair[0], pair[1])).collect()
+}
+
+/// Grey ramp from dark for the lowest band to light for the highest
+fn band_fill(index: usize, count: usize) -> String {
+    let shade = 48 + (160 * index) / count.saturating_sub(1).max(1);
+    format!("rgb({0},{0},{0})", shade)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn isoband_to_svg(img: &util::Image<f32>, thresholds: &[f32]) -> ContourResult<Svg> {
+    isobands_svg(&MarchingSquares::new(img), thresholds)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn isobands_svg(marching_squares: &MarchingSquares, thresholds: &[f32]) -> ContourResult<Svg> {
+    check_thresholds(thresholds)?;
+    let img = marching_squares.image();
+    let bounds = band_bounds(thresholds);
+
+    let band_to_path = |(i, (lower, upper)): (usize, &(f32, f32))| {
+        let isoband = marching_squares.isoband(*lower, *upper);
+        let polygons = isoband.polygons();
+        let rings = polygons
+            .iter()
+            .flat_map(|polygon| std::iter::once(&polygon.exterior).chain(&polygon.holes));
+        let path = paths_to_svg_path(rings, img, None);
+        SvgPath {
+            class: format!("band_{}_path", i),
+            fill: band_fill(i, bounds.len()),
+            path,
         }
     };
 
     Ok(Svg {
-        view_box: format!("0 0 {} {}", img.width, img.height),
-        paths: thresholds
+        view_box: svg_view_box(img),
+        paths: bounds
             .par_iter()
             .enumerate()
-            .map(threshold_to_path)
+            .map(band_to_path)
             .collect::<Vec<SvgPath>>(),
     })
 }
 
 #[cfg(target_arch = "wasm32")]
-fn isoline_to_svg(img: &util::Image<f32>, thresholds: &[f32]) -> Result<Svg, tiff::TiffError> {
-    let marching_squares = MarchingSquares::new(img);
+fn isoband_to_svg(img: &util::Image<f32>, thresholds: &[f32]) -> ContourResult<Svg> {
+    isobands_svg(&MarchingSquares::new(img), thresholds)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn isobands_svg(marching_squares: &MarchingSquares, thresholds: &[f32]) -> ContourResult<Svg> {
+    check_thresholds(thresholds)?;
+    let img = marching_squares.image();
+    let bounds = band_bounds(thresholds);
 
-    let threshold_to_path = |(i, threshold): (usize, &f32)| {
-        let isoline = marching_squares.isoline(*threshold);
-        let path: String = isoline
-            .paths
+    let band_to_path = |(i, (lower, upper)): (usize, &(f32, f32))| {
+        let isoband = marching_squares.isoband(*lower, *upper);
+        let polygons = isoband.polygons();
+        let rings = polygons
             .iter()
-            .map(|path| path_to_svg_path(path).join(" "))
-            .collect::<Vec<String>>()
-            .join(" ");
+            .flat_map(|polygon| std::iter::once(&polygon.exterior).chain(&polygon.holes));
+        let path = paths_to_svg_path(rings, img, None);
         SvgPath {
-            class: format!("threshold_{}_path", i),
-            fill: "none".to_string(),
-            path: path,
+            class: format!("band_{}_path", i),
+            fill: band_fill(i, bounds.len()),
+            path,
         }
     };
 
     Ok(Svg {
-        view_box: format!("0 0 {} {}", img.width, img.height),
-        paths: thresholds
+        view_box: svg_view_box(img),
+        paths: bounds
             .iter()
             .enumerate()
-            .map(threshold_to_path)
+            .map(band_to_path)
             .collect::<Vec<SvgPath>>(),
     })
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+pub fn isoline_to_geojson(
+    img: &util::Image<f32>,
+    thresholds: &[f32],
+) -> ContourResult<FeatureCollection> {
+    check_thresholds(thresholds)?;
+    let marching_squares = MarchingSquares::new(img);
+    let transform = img.geotransform.as_ref();
+
+    Ok(FeatureCollection::new(
+        marching_squares
+            .isolines(thresholds)
+            .par_iter()
+            .map(|isoline| geojson::isoline_feature(isoline, transform))
+            .collect(),
+    ))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn isoline_to_geojson(
+    img: &util::Image<f32>,
+    thresholds: &[f32],
+) -> ContourResult<FeatureCollection> {
+    check_thresholds(thresholds)?;
+    let marching_squares = MarchingSquares::new(img);
+    let transform = img.geotransform.as_ref();
+
+    Ok(FeatureCollection::new(
+        marching_squares
+            .isolines(thresholds)
+            .iter()
+            .map(|isoline| geojson::isoline_feature(isoline, transform))
+            .collect(),
+    ))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn isoband_to_geojson(
+    img: &util::Image<f32>,
+    thresholds: &[f32],
+) -> ContourResult<FeatureCollection> {
+    check_thresholds(thresholds)?;
+    let marching_squares = MarchingSquares::new(img);
+    let transform = img.geotransform.as_ref();
+
+    Ok(FeatureCollection::new(
+        band_bounds(thresholds)
+            .par_iter()
+            .map(|(lower, upper)| {
+                geojson::isoband_feature(&marching_squares.isoband(*lower, *upper), transform)
+            })
+            .collect(),
+    ))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn isoband_to_geojson(
+    img: &util::Image<f32>,
+    thresholds: &[f32],
+) -> ContourResult<FeatureCollection> {
+    check_thresholds(thresholds)?;
+    let marching_squares = MarchingSquares::new(img);
+    let transform = img.geotransform.as_ref();
+
+    Ok(FeatureCollection::new(
+        band_bounds(thresholds)
+            .iter()
+            .map(|(lower, upper)| {
+                geojson::isoband_feature(&marching_squares.isoband(*lower, *upper), transform)
+            })
+            .collect(),
+    ))
+}
+
 impl fmt::Display for Svg {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut lines = vec![format!(
@@ -142,9 +819,11 @@ impl fmt::Display for Svg {
         )];
 
         for path in &self.paths {
+            // Filled bands are drawn without an outline, isolines as a plain black stroke
+            let stroke = if path.fill == "none" { "black" } else { "none" };
             lines.push(format!(
-                "\t<path fill=\"none\" stroke=\"black\" stroke-width=\"1\" class=\"{}\" d=\"{}\" />",
-                path.class, path.path
+                "\t<path fill=\"{}\" fill-rule=\"evenodd\" stroke=\"{}\" stroke-width=\"1\" class=\"{}\" d=\"{}\" />",
+                path.fill, stroke, path.class, path.path
             ));
         }
         lines.push("</svg>".to_string());
@@ -154,10 +833,51 @@ impl fmt::Display for Svg {
 }
 
 pub fn path_to_svg_path(path: &Path) -> Vec<String> {
-    let mut svg_path = vec![format!("M{},{}", &path.points[0].x, &path.points[0].y)];
+    path_to_svg_commands(path, None, None)
+}
 
-    for point in &path.points[1..] {
-        svg_path.push(format!("L{},{}", point.x, point.y));
+// Georeferenced images are drawn in map coordinates, with y negated as SVG's y axis points down
+fn path_to_svg_commands(
+    path: &Path,
+    transform: Option<&GeoTransform>,
+    smoothing: Option<Smoothing>,
+) -> Vec<String> {
+    let coords = |point: &Point<f32>| match transform {
+        Some(transform) => {
+            let world = transform.sample_to_world(point);
+            format!("{},{}", world.x, -world.y)
+        }
+        None => format!("{},{}", point.x, point.y),
+    };
+
+    let cut;
+    let path = match smoothing {
+        Some(Smoothing::Chaikin { iterations }) => {
+            cut = path.chaikin(iterations);
+            &cut
+        }
+        _ => path,
+    };
+
+    let mut svg_path = vec![format!("M{}", coords(&path.points[0]))];
+
+    match smoothing {
+        // The transform is affine, so mapping the control points maps the whole curve
+        Some(Smoothing::CatmullRom { tension }) => {
+            for [control_start, control_end, end] in path.catmull_rom_beziers(tension) {
+                svg_path.push(format!(
+                    "C{} {} {}",
+                    coords(&control_start),
+                    coords(&control_end),
+                    coords(&end)
+                ));
+            }
+        }
+        _ => {
+            for point in &path.points[1..] {
+                svg_path.push(format!("L{}", coords(point)));
+            }
+        }
     }
 
     if path.circular {
@@ -166,6 +886,43 @@ pub fn path_to_svg_path(path: &Path) -> Vec<String> {
     svg_path
 }
 
+fn paths_to_svg_path<'a>(
+    paths: impl Iterator<Item = &'a Path>,
+    img: &util::Image<f32>,
+    smoothing: Option<Smoothing>,
+) -> String {
+    let transform = img.geotransform.as_ref();
+    paths
+        .map(|path| path_to_svg_commands(path, transform, smoothing).join(" "))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn svg_view_box(img: &util::Image<f32>) -> String {
+    let transform = match &img.geotransform {
+        Some(transform) => transform,
+        None => return format!("0 0 {} {}", img.width, img.height),
+    };
+
+    let (width, height) = (img.width as f64, img.height as f64);
+    let corners: Vec<Point<f64>> = [(0.0, 0.0), (width, 0.0), (0.0, height), (width, height)]
+        .iter()
+        .map(|(pixel, line)| transform.apply(*pixel, *line))
+        .collect();
+    let min_x = corners.iter().map(|c| c.x).fold(f64::INFINITY, f64::min);
+    let max_x = corners
+        .iter()
+        .map(|c| c.x)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let min_y = corners.iter().map(|c| c.y).fold(f64::INFINITY, f64::min);
+    let max_y = corners
+        .iter()
+        .map(|c| c.y)
+        .fold(f64::NEG_INFINITY, f64::max);
+
+    format!("{} {} {} {}", min_x, -max_y, max_x - min_x, max_y - min_y)
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -197,10 +954,10 @@ mod tests {
         let image = util::Image::new(data, 16, 16);
 
 
-        println!("{}", isoline_to_svg(&image, &vec![7.0]).unwrap());
-        println!("{}", isoline_to_svg(&image, &vec![5.0]).unwrap());
-        println!("{}", isoline_to_svg(&image, &vec![3.0]).unwrap());
-        println!("{}", isoline_to_svg(&image, &vec![3.0,5.0,7.0]).unwrap());
+        println!("{}", isoline_to_svg(&image, &[7.0], &IsolineOptions::default()).unwrap());
+        println!("{}", isoline_to_svg(&image, &[5.0], &IsolineOptions::default()).unwrap());
+        println!("{}", isoline_to_svg(&image, &[3.0], &IsolineOptions::default()).unwrap());
+        println!("{}", isoline_to_svg(&image, &[3.0,5.0,7.0], &IsolineOptions::default()).unwrap());
 
         let marching_squares = MarchingSquares::new(&image);
         let IsolineLayer{paths, threshold:_} = marching_squares.isoline(5.0);
@@ -210,6 +967,408 @@ mod tests {
         }
     }
 
+    #[test]
+    #[rustfmt::skip]
+    fn test_isoband() {
+        let data = [
+            1, 2, 3, 4, 4, 3, 2, 1,
+            2, 3, 4, 5, 5, 4, 3, 2,
+            3, 4, 5, 6, 6, 5, 4, 3,
+            4, 5, 6, 8, 8, 6, 5, 4,
+            4, 5, 6, 8, 8, 6, 5, 4,
+            3, 4, 5, 6, 6, 5, 4, 3,
+            2, 3, 4, 5, 5, 4, 3, 2,
+            1, 2, 3, 4, 4, 3, 2, 1,
+        ].iter().map(|num| *num as f32).collect::<Vec<f32>>();
+
+        let svg = isoband(data, 8, 8, &[7.0, 3.0, 5.0]).unwrap();
+        println!("{}", svg);
+
+        assert_eq!(svg.paths.len(), 2);
+        assert_eq!(svg.paths[0].class, "band_0_path");
+        assert_eq!(svg.paths[0].fill, "rgb(48,48,48)");
+        assert_eq!(svg.paths[1].fill, "rgb(208,208,208)");
+        assert!(svg.paths.iter().all(|path| path.path.ends_with('Z')));
+    }
+
+    use tiff::encoder::{colortype, TiffEncoder};
+    use tiff::tags::Tag;
+
+    #[test]
+    fn test_tiff_nodata_and_scale() {
+        let mut buffer = Cursor::new(Vec::new());
+        let mut encoder = TiffEncoder::new(&mut buffer).unwrap();
+        let mut tiff_image = encoder.new_image::<colortype::Gray16>(2, 2).unwrap();
+        tiff_image
+            .encoder()
+            .write_tag(Tag::GdalNodata, "65535")
+            .unwrap();
+        tiff_image
+            .encoder()
+            .write_tag(
+                Tag::Unknown(42112),
+                "<GDALMetadata><Item name=\"SCALE\" sample=\"0\" role=\"scale\">0.5</Item>\
+                 <Item name=\"OFFSET\" sample=\"0\" role=\"offset\">-100</Item></GDALMetadata>",
+            )
+            .unwrap();
+        tiff_image.write_data(&[200, 300, 65535, 400]).unwrap();
+
+        let image = bytes_to_image(buffer.get_ref()).unwrap();
+        assert_eq!(image.get_val(&Point { x: 0, y: 0 }), Some(0.0));
+        assert_eq!(image.get_val(&Point { x: 1, y: 0 }), Some(50.0));
+        assert_eq!(image.get_val(&Point { x: 0, y: 1 }), None);
+        assert_eq!(image.get_val(&Point { x: 1, y: 1 }), Some(100.0));
+    }
+
+    #[test]
+    fn test_tiff_georeferenced() {
+        let mut buffer = Cursor::new(Vec::new());
+        let mut encoder = TiffEncoder::new(&mut buffer).unwrap();
+        let mut tiff_image = encoder.new_image::<colortype::Gray16>(3, 3).unwrap();
+        tiff_image
+            .encoder()
+            .write_tag(Tag::ModelPixelScaleTag, &[10.0, 10.0, 0.0][..])
+            .unwrap();
+        tiff_image
+            .encoder()
+            .write_tag(
+                Tag::ModelTiepointTag,
+                &[0.0, 0.0, 0.0, 1000.0, 2000.0, 0.0][..],
+            )
+            .unwrap();
+        tiff_image
+            .encoder()
+            .write_tag(Tag::GeoKeyDirectoryTag, &[1u16, 1, 0, 1, 1025, 0, 1, 1][..])
+            .unwrap();
+        #[rustfmt::skip]
+        tiff_image.write_data(&[
+            0, 0, 0,
+            0, 10, 0,
+            0, 0, 0,
+        ]).unwrap();
+
+        let image = bytes_to_image(buffer.get_ref()).unwrap();
+        assert_eq!(
+            image.geotransform,
+            Some(GeoTransform([1000.0, 10.0, 0.0, 2000.0, 0.0, -10.0]))
+        );
+
+        let svg = isoline_to_svg(&image, &[5.0], &IsolineOptions::default()).unwrap();
+        assert_eq!(svg.view_box, "1000 -2000 30 30");
+        assert!(svg.paths[0].path.starts_with("M10"));
+        assert!(svg.paths[0].path.contains("L1015,-1990"));
+    }
+
+    #[test]
+    fn test_geojson() {
+        #[rustfmt::skip]
+        let data = [
+            0.0, 0.0, 0.0, 0.0,
+            0.0, 10.0, 10.0, 0.0,
+            0.0, 0.0, 0.0, 0.0,
+        ];
+        let image = util::Image::new(data.to_vec(), 4, 3);
+
+        let isolines = isoline_to_geojson(&image, &[5.0]).unwrap();
+        assert_eq!(isolines.features.len(), 1);
+        assert_eq!(
+            isolines.features[0].properties,
+            Properties::Isoline { threshold: 5.0 }
+        );
+        match &isolines.features[0].geometry {
+            Geometry::LineString(line) => {
+                assert_eq!(line.len(), 7);
+                assert_eq!(line.first(), line.last());
+            }
+            geometry => panic!("expected a single closed line, got {:?}", geometry),
+        }
+
+        let isobands = isoband_to_geojson(&image, &[10.0, 5.0, 0.0]).unwrap();
+        assert_eq!(isobands.features.len(), 2);
+        assert_eq!(
+            isobands.features[1].properties,
+            Properties::Isoband {
+                lower: 5.0,
+                upper: 10.0
+            }
+        );
+        assert!(isobands
+            .to_string()
+            .starts_with("{\"type\":\"FeatureCollection\",\"features\":[{\"type\":\"Feature\""));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(matches!(
+            isoline(vec![0.0; 5], 3, 2, &[1.0]),
+            Err(ContourError::DimensionMismatch {
+                width: 3,
+                height: 2,
+                samples: 5
+            })
+        ));
+        assert!(matches!(
+            isoband(Vec::new(), 0, 0, &[1.0, 2.0]),
+            Err(ContourError::InvalidData(_))
+        ));
+        assert!(matches!(
+            isoline(vec![0.0; 4], 2, 2, &[f32::NAN]),
+            Err(ContourError::InvalidData(_))
+        ));
+        assert!(matches!(
+            isoline_from_tiff(b"not a tiff", &[1.0]),
+            Err(ContourError::Decode(_))
+        ));
+
+        // Three samples per pixel decode to three times as many values as there are pixels
+        let mut buffer = Cursor::new(Vec::new());
+        let mut encoder = TiffEncoder::new(&mut buffer).unwrap();
+        let tiff_image = encoder.new_image::<colortype::RGB8>(2, 1).unwrap();
+        tiff_image.write_data(&[1, 1, 1, 2, 2, 2]).unwrap();
+        assert!(matches!(
+            isoline_from_tiff(buffer.get_ref(), &[1.0]),
+            Err(ContourError::DimensionMismatch { samples: 6, .. })
+        ));
+    }
+
+    /// `x² + y²` on an `n` by `n` grid, measured from its centre
+    fn paraboloid(n: u32) -> Vec<f32> {
+        let centre = (n - 1) as f32 / 2.0;
+        (0..n * n)
+            .map(|i| {
+                let (x, y) = ((i % n) as f32 - centre, (i / n) as f32 - centre);
+                x * x + y * y
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_contour_generator() {
+        let squares = &IsolineOptions::default();
+        let data = paraboloid(8);
+        let image = util::Image::new(data.clone(), 8, 8);
+        let generator = ContourGenerator::new(data, 8, 8).unwrap();
+
+        assert_eq!(
+            generator.isoline(5.0).unwrap().to_string(),
+            isoline_to_svg(&image, &[5.0], squares).unwrap().to_string()
+        );
+        for thresholds in &[vec![2.0, 8.0], vec![4.0, 12.0, 20.0]] {
+            assert_eq!(
+                generator.isolines(thresholds).unwrap().to_string(),
+                isoline_to_svg(&image, thresholds, squares)
+                    .unwrap()
+                    .to_string()
+            );
+            assert_eq!(
+                generator.isobands(thresholds).unwrap().to_string(),
+                isoband_to_svg(&image, thresholds).unwrap().to_string()
+            );
+        }
+
+        let geometry = generator.isoline_geometry(&[2.0, 8.0]).unwrap();
+        assert_eq!(geometry.path_count(), 2);
+        assert_eq!(geometry.closed(), [1, 1]);
+        assert_eq!(geometry.threshold_indices(), [0, 1]);
+        assert_eq!(
+            geometry.coords().len(),
+            2 * *geometry.offsets().last().unwrap() as usize
+        );
+        let options = IsolineOptions::default();
+        let geometry = isoline_to_geometry(&image, &[8.0], &options).unwrap();
+        assert_eq!(geometry.path_count(), 1);
+        let options = IsolineOptions {
+            upsample: 4,
+            ..IsolineOptions::default()
+        };
+        let upsampled = isoline_to_geometry(&image, &[8.0], &options).unwrap();
+        assert_eq!(upsampled.path_count(), 1);
+        assert!(upsampled.coords().len() > geometry.coords().len());
+
+        assert!(matches!(
+            generator.isoline(f32::INFINITY),
+            Err(ContourError::InvalidData(_))
+        ));
+        assert!(matches!(
+            ContourGenerator::new(vec![0.0; 3], 2, 2),
+            Err(ContourError::DimensionMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_contour_mode() {
+        let data = paraboloid(8);
+        let image = util::Image::new(data, 8, 8);
+
+        assert_eq!(
+            ContourMode::from_name("squares").unwrap(),
+            ContourMode::default()
+        );
+        let triangles = ContourMode::from_name("triangles").unwrap();
+        assert_eq!(triangles, ContourMode::Triangles(Diagonal::Fixed));
+        assert!(matches!(
+            ContourMode::from_name("hexagons"),
+            Err(ContourError::InvalidData(_))
+        ));
+
+        // Same rings, with extra points where they cross the diagonals
+        let thresholds = [2.0, 8.0];
+        let squares = isoline_to_svg(&image, &thresholds, &IsolineOptions::default()).unwrap();
+        let options = IsolineOptions {
+            mode: triangles,
+            ..IsolineOptions::default()
+        };
+        let triangles = isoline_to_svg(&image, &thresholds, &options).unwrap();
+        assert_eq!(triangles.view_box, squares.view_box);
+        for (triangle, square) in triangles.paths.iter().zip(&squares.paths) {
+            assert_eq!(triangle.path.matches('Z').count(), 1);
+            assert!(triangle.path.matches('L').count() > square.path.matches('L').count());
+        }
+        assert!(matches!(
+            isoline_to_svg(&image, &[f32::NAN], &IsolineOptions::default()),
+            Err(ContourError::InvalidData(_))
+        ));
+    }
+
+    #[test]
+    fn test_upsampled_isolines() {
+        // An odd size centres it on a sample, as the spline is clamped flat between two equal
+        // samples
+        let data = paraboloid(9);
+        let image = util::Image::new(data, 9, 9);
+        let worst_error = |upsample: u32| {
+            let options = IsolineOptions {
+                upsample,
+                ..IsolineOptions::default()
+            };
+            let layers = options.isolines(&image, &[8.0]).unwrap();
+            assert_eq!(layers[0].paths.len(), 1);
+            let ring = &layers[0].paths[0];
+            assert!(ring.circular);
+            let error = ring
+                .points
+                .iter()
+                .map(|point| ((point.x - 4.0).hypot(point.y - 4.0) - 8f32.sqrt()).abs())
+                .fold(0.0, f32::max);
+            (ring.points.len(), error)
+        };
+
+        // A circle of radius √8 in the original sample space, with more points and closer to it
+        let (coarse_points, coarse_error) = worst_error(1);
+        let (fine_points, fine_error) = worst_error(4);
+        assert!(fine_points > 3 * coarse_points);
+        assert!(
+            fine_error < coarse_error / 2.0,
+            "{} {}",
+            fine_error,
+            coarse_error
+        );
+
+        let options = IsolineOptions {
+            upsample: 0,
+            ..IsolineOptions::default()
+        };
+        assert!(matches!(
+            isoline_to_svg(&image, &[8.0], &options),
+            Err(ContourError::InvalidData(_))
+        ));
+
+        // Each side fits a u32 but the whole upsampled image doesn't
+        let options = IsolineOptions {
+            upsample: 10_000,
+            ..IsolineOptions::default()
+        };
+        assert!(matches!(
+            options.isolines(&image, &[8.0]),
+            Err(ContourError::InvalidData(_))
+        ));
+    }
+
+    #[test]
+    fn test_smoothed_svg() {
+        let data = paraboloid(8);
+        let image = util::Image::new(data, 8, 8);
+        let svg = |smoothing| {
+            let options = IsolineOptions {
+                smoothing,
+                ..IsolineOptions::default()
+            };
+            isoline_to_svg(&image, &[8.0, 20.0], &options).unwrap()
+        };
+
+        let lines = svg(None);
+        let curves = svg(Some(Smoothing::CatmullRom { tension: 0.5 }));
+        let cut = svg(Some(Smoothing::Chaikin { iterations: 2 }));
+
+        // The ring is drawn as one curve per point, and is still closed
+        let ring = &curves.paths[0].path;
+        assert!(ring.starts_with('M') && ring.ends_with('Z'));
+        assert!(!ring.contains('L'));
+        assert_eq!(
+            ring.matches('C').count(),
+            lines.paths[0].path.matches('L').count()
+        );
+        assert!(cut.paths[0].path.matches('L').count() > lines.paths[0].path.matches('L').count());
+
+        // Lines cutting off the corners of the image start and end where they did
+        let ends = |path: &str| {
+            let commands: Vec<String> = path
+                .split(['M', 'L', 'C', ' '])
+                .filter(|coords| !coords.is_empty())
+                .map(str::to_string)
+                .collect();
+            (commands[0].clone(), commands[commands.len() - 1].clone())
+        };
+        for smoothed in [&curves, &cut] {
+            for (smoothed, line) in smoothed.paths[1]
+                .path
+                .split(" M")
+                .zip(lines.paths[1].path.split(" M"))
+            {
+                assert_eq!(ends(smoothed), ends(line));
+            }
+        }
+    }
+
+    #[test]
+    fn test_simplified_isolines() {
+        let data = paraboloid(64);
+        let image = util::Image::new(data, 64, 64);
+        let thresholds = [400.0, 1500.0];
+        let point_count = |options: &IsolineOptions| -> usize {
+            let layers = options.isolines(&image, &thresholds).unwrap();
+            assert!(layers.iter().all(|layer| !layer.paths.is_empty()));
+            layers
+                .iter()
+                .flat_map(|layer| &layer.paths)
+                .map(|path| path.points.len())
+                .sum()
+        };
+
+        let traced = point_count(&IsolineOptions::default());
+        for simplification in [
+            Simplification::DouglasPeucker { tolerance: 0.25 },
+            Simplification::VisvalingamWhyatt { min_area: 0.25 },
+        ] {
+            let options = IsolineOptions {
+                simplification: Some(simplification),
+                ..IsolineOptions::default()
+            };
+            assert!(point_count(&options) * 3 < traced, "{:?}", simplification);
+
+            // Keeping the levels apart only ever costs a few points here
+            let preserved = IsolineOptions {
+                preserve_topology: true,
+                ..options
+            };
+            assert!(point_count(&preserved) * 3 < traced, "{:?}", simplification);
+
+            let svg = isoline_to_svg(&image, &thresholds, &options).unwrap();
+            assert_eq!(svg.paths.len(), 2);
+            assert!(svg.paths[0].path.ends_with('Z'));
+        }
+    }
+
     use std::fs::File;
     use std::io::prelude::*;
 
@@ -220,7 +1379,7 @@ mod tests {
 
         f.read_to_end(&mut buffer).unwrap();
 
-        let svg: Svg = isoline_from_tiff(&buffer, &vec![25.0, 50.0, 75.0, 100.0]);
+        let svg: Svg = isoline_from_tiff(&buffer, &[25.0, 50.0, 75.0, 100.0]).unwrap();
         println!("{}", svg);
     }
 }
@@ -1,15 +1,32 @@
-use super::quad_tree::*;
+use super::polygon::*;
+use super::pyramid::*;
 use super::util::*;
 use num::{Integer, NumCast};
+use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
 
+#[cfg(not(target_arch = "wasm32"))]
+use rayon::prelude::*;
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::prelude::*;
+
 #[derive(Clone, Debug, Eq, Hash, PartialEq, PartialOrd)]
 pub struct Path {
     pub points: Vec<Point<f32>>,
     pub circular: bool,
 }
 
+/// A path through `points`, for tests to write out by hand
+#[cfg(test)]
+pub(crate) fn path(points: &[(f32, f32)], circular: bool) -> Path {
+    Path {
+        points: points.iter().map(|(x, y)| Point { x: *x, y: *y }).collect(),
+        circular,
+    }
+}
+
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd)]
 enum Direction {
     Up,
@@ -24,14 +41,80 @@ pub struct IsolineLayer {
     pub paths: Vec<Path>,
 }
 
+#[derive(Clone, Debug, PartialEq)]
+pub struct IsobandLayer {
+    pub lower: f32,
+    pub upper: f32,
+    pub paths: Vec<Path>,
+}
+
+impl IsolineLayer {
+    /// Closed isolines grouped into polygons with holes, open isolines are left out
+    pub fn polygons(&self) -> Vec<Polygon> {
+        assemble_polygons(&self.paths)
+    }
+}
+
+impl IsobandLayer {
+    pub fn polygons(&self) -> Vec<Polygon> {
+        assemble_polygons(&self.paths)
+    }
+}
+
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd)]
 struct Segment {
-    start: Point<f32>,
-    end: Point<f32>,
+    start: Vertex,
+    end: Vertex,
     cell_coord: Point<u32>,
     direction: Direction,
 }
 
+/// Where a contour crosses the edge between two neighbouring samples
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd)]
+struct Vertex {
+    point: Point<f32>,
+    edge: GridEdge,
+}
+
+/// The edge between two neighbouring samples, as an exact key for linking segments
+///
+/// Horizontal edges run from `(x, y)` to `(x + 1, y)` and vertical edges from `(x, y)` to
+/// `(x, y + 1)`, so the cells on either side of an edge agree on its key.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd)]
+enum GridEdge {
+    Horizontal(Point<u32>),
+    Vertical(Point<u32>),
+}
+
+/// A path traced within part of the image, with the segments at either end of it
+#[derive(Clone, Debug)]
+pub(crate) struct Fragment {
+    path: Path,
+    first: Segment,
+    last: Segment,
+}
+
+impl Fragment {
+    // Cells and edges are numbered within the image that was traced, while the points
+    // already include its origin
+    fn with_origin(mut self, origin: &Point<u32>) -> Fragment {
+        for segment in [&mut self.first, &mut self.last] {
+            segment.cell_coord = segment.cell_coord + origin;
+            for vertex in [&mut segment.start, &mut segment.end] {
+                vertex.edge = match vertex.edge {
+                    GridEdge::Horizontal(start) => GridEdge::Horizontal(start + origin),
+                    GridEdge::Vertical(start) => GridEdge::Vertical(start + origin),
+                };
+            }
+        }
+        self
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+const MIN_CHUNK_ROWS: u32 = 64;
+
+#[derive(Clone, Copy)]
 enum CellIndex {
     TopLeft = 0,
     TopRight = 1,
@@ -46,75 +129,171 @@ const CELL_OFFSETS: [Point<u32>; 4] = [
     Point { x: 1, y: 1 },
 ];
 
+/// How to join the contours through a saddle cell, where diagonally opposite corners are on
+/// the same side of the threshold and the corners alone don't say which pair is connected
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum SaddleStrategy {
+    /// Compare the average of the four corners with the threshold
+    #[default]
+    CentreAverage,
+    /// Compare the value at the saddle point of the bilinear surface through the corners
+    /// with the threshold, which agrees with the cells around it on smooth data
+    AsymptoticDecider,
+    /// Always connect the corners above the threshold
+    JoinHigh,
+    /// Always connect the corners below the threshold
+    JoinLow,
+}
+
+impl SaddleStrategy {
+    /// Whether the middle of a saddle cell counts as above the threshold, joining the high
+    /// corners across it, given the corners in `CELL_OFFSETS` order
+    fn centre_above(self, corners: [f32; 4], threshold: f32) -> bool {
+        let [top_left, top_right, bottom_left, bottom_right] = corners;
+        match self {
+            SaddleStrategy::CentreAverage => corners.iter().sum::<f32>() / 4.0 > threshold,
+            SaddleStrategy::AsymptoticDecider => {
+                // Never zero in a saddle, one diagonal is above the threshold and one below
+                let saddle = (top_left * bottom_right - top_right * bottom_left)
+                    / (top_left + bottom_right - top_right - bottom_left);
+                saddle > threshold
+            }
+            SaddleStrategy::JoinHigh => true,
+            SaddleStrategy::JoinLow => false,
+        }
+    }
+}
+
 pub struct MarchingSquares<'a> {
-    img: &'a Image<f32>,
-    quad_tree: TreeNode,
+    img: Cow<'a, Image<f32>>,
+    pyramid: MinMaxPyramid,
+    origin: Point<u32>,
+    saddle: SaddleStrategy,
 }
 
 impl MarchingSquares<'_> {
     pub fn new<'a>(img: &'a Image<f32>) -> MarchingSquares<'a> {
-        let quad_tree = TreeNode::create(img);
+        let pyramid = MinMaxPyramid::create(img);
         MarchingSquares {
-            img: img,
-            quad_tree: quad_tree,
+            img: Cow::Borrowed(img),
+            pyramid,
+            origin: Point { x: 0, y: 0 },
+            saddle: SaddleStrategy::default(),
         }
     }
 
-    fn cell_state(&self, cell: &Point<u32>, threshold: f32) -> u8 {
-        // Save the state of the 4 corners of the cell
-        // cell_state will be a 4-bit binary number with each digit corresponding to the corner in the order of offsets
-        //      with a 0 indicating the corner is below the threshold, a 1 indicating above the threshold
-        // cell_state = 0b<top_left><top_right><bottom_left><bottom_right>
-        let mut cell_state = 0;
-        for offset in &CELL_OFFSETS {
-            let corner_state = match self.img.get_val(&(cell + offset)) {
-                Some(val) => {
-                    if val >= threshold {
-                        1
-                    } else {
-                        0
-                    }
-                }
-                None => 0,
-            };
-            cell_state = (cell_state << 1) + corner_state;
+    /// Take ownership of the image, so the contourer can be kept around on its own
+    pub fn from_image(img: Image<f32>) -> MarchingSquares<'static> {
+        let pyramid = MinMaxPyramid::create(&img);
+        MarchingSquares {
+            img: Cow::Owned(img),
+            pyramid,
+            origin: Point { x: 0, y: 0 },
+            saddle: SaddleStrategy::default(),
+        }
+    }
+
+    /// Treat the image as a piece of a larger one with its top left sample at `origin`
+    ///
+    /// Contours come out in the larger image's coordinates, interpolated exactly as they
+    /// would be from the whole image, so pieces contoured separately line up.
+    pub fn with_origin(mut self, origin: Point<u32>) -> Self {
+        self.origin = origin;
+        self
+    }
+
+    /// Resolve saddle cells with `saddle` rather than by the average of their corners
+    pub fn with_saddle_strategy(mut self, saddle: SaddleStrategy) -> Self {
+        self.set_saddle_strategy(saddle);
+        self
+    }
+
+    pub(crate) fn set_saddle_strategy(&mut self, saddle: SaddleStrategy) {
+        self.saddle = saddle;
+    }
+
+    pub fn image(&self) -> &Image<f32> {
+        &self.img
+    }
+
+    fn cell_values(&self, cell: &Point<u32>) -> [Option<f32>; 4] {
+        let mut vals = [None; 4];
+        for (val, offset) in vals.iter_mut().zip(&CELL_OFFSETS) {
+            *val = self.img.get_val(&(cell + offset));
         }
-        cell_state
+        vals
+    }
+
+    #[cfg(test)]
+    fn cell_state(&self, cell: &Point<u32>, threshold: f32) -> u8 {
+        values_to_state(&self.cell_values(cell), threshold)
+    }
+
+    // A corner inside the image without a value, as opposed to one off its edge
+    fn has_missing_corner(&self, cell: &Point<u32>, vals: &[Option<f32>; 4]) -> bool {
+        CELL_OFFSETS
+            .iter()
+            .zip(vals)
+            .any(|(offset, val)| val.is_none() && self.img.contains(&(cell + offset)))
     }
 
     fn cell_to_segments(&self, cell: &Point<u32>, threshold: f32) -> Vec<Segment> {
-        let cell_state = self.cell_state(&cell, threshold);
-        let cells: Vec<Point<u32>> = CELL_OFFSETS.iter().map(|offset| cell + offset).collect();
-        let vals: Vec<Option<f32>> = cells.iter().map(|coord| self.img.get_val(&coord)).collect();
+        self.values_to_segments(cell, &self.cell_values(cell), threshold)
+    }
+
+    fn values_to_segments(
+        &self,
+        cell: &Point<u32>,
+        vals: &[Option<f32>; 4],
+        threshold: f32,
+    ) -> Vec<Segment> {
+        let cell_state = values_to_state(vals, threshold);
+        let cells: Vec<Point<u32>> = CELL_OFFSETS
+            .iter()
+            .map(|offset| cell + offset + self.origin)
+            .collect();
+
+        // Leave contours open where they run into missing data rather than guessing at a value,
+        // corners off the edge of the image count as below the threshold
+        if self.has_missing_corner(cell, vals) {
+            return Vec::new();
+        }
+        let corners = vals.map(Option::unwrap_or_default);
 
         let t_bottom = dist_between_option_values(
-            threshold as f32,
+            threshold,
             &vals[CellIndex::BottomLeft as usize],
             &vals[CellIndex::BottomRight as usize],
         );
         let t_right = dist_between_option_values(
-            threshold as f32,
+            threshold,
             &vals[CellIndex::TopRight as usize],
             &vals[CellIndex::BottomRight as usize],
         );
         let t_left = dist_between_option_values(
-            threshold as f32,
-            &vals[CellIndex::BottomLeft as usize],
+            threshold,
             &vals[CellIndex::TopLeft as usize],
+            &vals[CellIndex::BottomLeft as usize],
         );
         let t_top = dist_between_option_values(
-            threshold as f32,
+            threshold,
             &vals[CellIndex::TopLeft as usize],
             &vals[CellIndex::TopRight as usize],
         );
 
+        // Every edge is interpolated from its top or left sample, so the two cells sharing
+        // an edge place the vertex on it at exactly the same point
         let left = || {
-            let c = cells[CellIndex::BottomLeft as usize];
-            let c2 = cells[CellIndex::TopLeft as usize];
-
-            Point {
-                x: c.x as f32,
-                y: interpolate(t_left, c.y, c2.y),
+            let c = cells[CellIndex::TopLeft as usize];
+            let c2 = cells[CellIndex::BottomLeft as usize];
+
+            Vertex {
+                point: Point {
+                    x: c.x as f32,
+                    y: interpolate(t_left, c.y, c2.y),
+                },
+                edge: GridEdge::Vertical(*cell),
             }
         };
 
@@ -122,9 +301,12 @@ impl MarchingSquares<'_> {
             let c = cells[CellIndex::TopLeft as usize];
             let c2 = cells[CellIndex::TopRight as usize];
 
-            Point {
-                x: interpolate(t_top, c.x, c2.x),
-                y: c.y as f32,
+            Vertex {
+                point: Point {
+                    x: interpolate(t_top, c.x, c2.x),
+                    y: c.y as f32,
+                },
+                edge: GridEdge::Horizontal(*cell),
             }
         };
 
@@ -132,9 +314,12 @@ impl MarchingSquares<'_> {
             let c = cells[CellIndex::BottomLeft as usize];
             let c2 = cells[CellIndex::BottomRight as usize];
 
-            Point {
-                x: interpolate(t_bottom, c.x, c2.x),
-                y: c.y as f32,
+            Vertex {
+                point: Point {
+                    x: interpolate(t_bottom, c.x, c2.x),
+                    y: c.y as f32,
+                },
+                edge: GridEdge::Horizontal(cell + CELL_OFFSETS[CellIndex::BottomLeft as usize]),
             }
         };
 
@@ -142,9 +327,12 @@ impl MarchingSquares<'_> {
             let c = cells[CellIndex::TopRight as usize];
             let c2 = cells[CellIndex::BottomRight as usize];
 
-            Point {
-                x: c.x as f32,
-                y: interpolate(t_right, c.y, c2.y),
+            Vertex {
+                point: Point {
+                    x: c.x as f32,
+                    y: interpolate(t_right, c.y, c2.y),
+                },
+                edge: GridEdge::Vertical(cell + CELL_OFFSETS[CellIndex::TopRight as usize]),
             }
         };
         // Segments should go clockwise with above threshold on the inside
@@ -254,12 +442,10 @@ impl MarchingSquares<'_> {
             // o - x
             // x - o
             0b0110 => {
-                let avg_val = vals.iter().flatten().sum::<f32>() / vals.len() as f32;
-
                 // o - - - x
                 // |   x   |
                 // x - - - o
-                if avg_val > threshold as f32 {
+                if self.saddle.centre_above(corners, threshold) {
                     vec![
                         Segment {
                             start: left(),
@@ -297,12 +483,10 @@ impl MarchingSquares<'_> {
             // x - o
             // o - x
             0b1001 => {
-                let avg_val = vals.iter().flatten().sum::<f32>() / vals.len() as f32;
-
                 // x - - - o
                 // |   x   |
                 // o - - - x
-                if avg_val > threshold as f32 {
+                if self.saddle.centre_above(corners, threshold) {
                     vec![
                         Segment {
                             start: top(),
@@ -338,19 +522,19 @@ impl MarchingSquares<'_> {
                 }
             }
 
-            0b1111 | 0b0000 => Vec::new(),
-            _ => panic!("Not a valid cell state"),
+            // 0b0000 and 0b1111, the whole cell is on one side of the threshold
+            _ => Vec::new(),
         }
     }
 
     fn segments_for_threshold(&self, threshold: f32) -> HashMap<Point<u32>, Vec<Segment>> {
         let mut segment_map: HashMap<Point<u32>, Vec<Segment>> = HashMap::new();
-        let cells = self.quad_tree.above_threshold(threshold);
+        let cells = self.pyramid.crossing(threshold);
         for cell in cells
             .iter()
             .map(|cell| self.cell_to_segments(cell, threshold))
         {
-            if let Some(segment) = cell.get(0) {
+            if let Some(segment) = cell.first() {
                 segment_map.insert(segment.cell_coord, cell);
             }
         }
@@ -360,9 +544,371 @@ impl MarchingSquares<'_> {
 
     pub fn isoline(&self, threshold: f32) -> IsolineLayer {
         let cell_segments = self.segments_for_threshold(threshold);
-        let paths = trace_segments(&cell_segments);
+        let mut paths = trace_segments(&cell_segments);
+        canonical_order(&mut paths);
         IsolineLayer { threshold, paths }
     }
+
+    /// One layer per threshold, in the order given, from a single pass over the cells
+    ///
+    /// Each cell is read once and emits segments for every threshold between its
+    /// smallest and largest corner, rather than being revisited for each level.
+    pub fn isolines(&self, thresholds: &[f32]) -> Vec<IsolineLayer> {
+        self.isolines_in_chunks(thresholds, chunk_rows(self.img.height))
+    }
+
+    // Contours chunks of `rows` cell rows in parallel, leaving a path in pieces wherever it
+    // crosses from one chunk into the next, then stitches the pieces back together
+    fn isolines_in_chunks(&self, thresholds: &[f32], rows: u32) -> Vec<IsolineLayer> {
+        let mut sorted: Vec<(usize, f32)> = thresholds.iter().copied().enumerate().collect();
+        sorted.sort_by(|a, b| a.1.total_cmp(&b.1));
+        let (lowest, highest) = match (sorted.first(), sorted.last()) {
+            (Some(first), Some(last)) => (first.1, last.1),
+            _ => return Vec::new(),
+        };
+
+        // A cell crosses a threshold when lower < threshold <= upper, so the cells crossing
+        // any of them are the ones overlapping the range from the lowest to the highest
+        let cell_rows = self.img.height.saturating_sub(1).max(1);
+        let mut chunks: Vec<Vec<Point<u32>>> = vec![Vec::new(); cell_rows.div_ceil(rows) as usize];
+        for cell in self.pyramid.within_range(lowest, highest) {
+            chunks[(cell.y / rows) as usize].push(cell);
+        }
+
+        let chunk_fragments = map_parallel(chunks, |cells| {
+            self.segments_for_cells(&cells, &sorted)
+                .iter()
+                .map(trace_fragments)
+                .collect::<Vec<Vec<Fragment>>>()
+        });
+        let mut levels: Vec<Vec<Fragment>> = vec![Vec::new(); sorted.len()];
+        for fragments in chunk_fragments {
+            for (level, fragments) in levels.iter_mut().zip(fragments) {
+                level.extend(fragments);
+            }
+        }
+
+        let mut layers: Vec<Option<IsolineLayer>> = vec![None; sorted.len()];
+        for ((index, threshold), paths) in sorted.iter().zip(map_parallel(levels, stitch_fragments))
+        {
+            layers[*index] = Some(IsolineLayer {
+                threshold: *threshold,
+                paths,
+            });
+        }
+        layers.into_iter().flatten().collect()
+    }
+
+    /// Pieces of the isolines at each threshold, in the order given, left open where they run
+    /// off this image so `stitch_fragments` can join them to pieces from the rest of a larger
+    /// image that this one is part of
+    pub(crate) fn isoline_fragments(&self, thresholds: &[f32]) -> Vec<Vec<Fragment>> {
+        let mut sorted: Vec<(usize, f32)> = thresholds.iter().copied().enumerate().collect();
+        sorted.sort_by(|a, b| a.1.total_cmp(&b.1));
+        let (lowest, highest) = match (sorted.first(), sorted.last()) {
+            (Some(first), Some(last)) => (first.1, last.1),
+            _ => return Vec::new(),
+        };
+
+        let cells = self.pyramid.within_range(lowest, highest);
+        let mut fragments = vec![Vec::new(); sorted.len()];
+        for ((index, _), segments) in sorted.iter().zip(self.segments_for_cells(&cells, &sorted)) {
+            fragments[*index] = trace_fragments(&segments)
+                .into_iter()
+                .map(|fragment| fragment.with_origin(&self.origin))
+                .collect();
+        }
+        fragments
+    }
+
+    // Segments of the cells for each of the sorted thresholds
+    fn segments_for_cells(
+        &self,
+        cells: &[Point<u32>],
+        sorted: &[(usize, f32)],
+    ) -> Vec<HashMap<Point<u32>, Vec<Segment>>> {
+        let mut levels: Vec<HashMap<Point<u32>, Vec<Segment>>> = vec![HashMap::new(); sorted.len()];
+        for cell in cells {
+            let vals = self.cell_values(cell);
+            // Missing corners never produce segments
+            if self.has_missing_corner(cell, &vals) {
+                continue;
+            }
+            let (lower, upper) = values_range(&vals);
+
+            let first = sorted.partition_point(|(_, threshold)| *threshold <= lower);
+            for (level, (_, threshold)) in sorted.iter().enumerate().skip(first) {
+                if *threshold > upper {
+                    break;
+                }
+                let segments = self.values_to_segments(cell, &vals, *threshold);
+                if !segments.is_empty() {
+                    levels[level].insert(*cell, segments);
+                }
+            }
+        }
+        levels
+    }
+
+    fn band_state(&self, cell: &Point<u32>, lower: f32, upper: f32) -> Option<u8> {
+        // Ternary counterpart to cell_state, indexing the 81 isoband configurations
+        // Each digit is a corner in the order of offsets, 0 below the band, 1 inside it, 2 above it
+        let mut band_state = 0;
+        for offset in &CELL_OFFSETS {
+            let val = self.img.get_val(&(cell + offset))?;
+            band_state = band_state * 3 + band_level(val, lower, upper);
+        }
+        Some(band_state)
+    }
+
+    fn cell_to_band_edges(
+        &self,
+        cell: &Point<u32>,
+        lower: f32,
+        upper: f32,
+    ) -> Vec<(Point<f32>, Point<f32>)> {
+        let band_state = match self.band_state(cell, lower, upper) {
+            Some(state) => state,
+            None => return Vec::new(),
+        };
+
+        let cells: Vec<Point<u32>> = PERIMETER
+            .iter()
+            .map(|index| cell + CELL_OFFSETS[*index as usize])
+            .collect();
+        let vals: Vec<f32> = cells
+            .iter()
+            .flat_map(|coord| self.img.get_val(coord))
+            .collect();
+        let levels: Vec<u8> = vals
+            .iter()
+            .map(|val| band_level(*val, lower, upper))
+            .collect();
+        let cells: Vec<Point<u32>> = cells.iter().map(|coord| coord + self.origin).collect();
+
+        let vertices = match band_state {
+            // Entirely below or above the band
+            0 | 80 => return Vec::new(),
+            // Entirely inside the band, the whole cell is one polygon
+            40 => return ring_edges(&cells_to_points(&cells)),
+            _ => band_vertices(&cells, &vals, &levels, lower, upper),
+        };
+
+        // Pair up the crossings of each threshold with a chord through the cell,
+        // resolving saddles the same way as cell_to_segments does
+        let mut partners: Vec<Option<usize>> = vec![None; vertices.len()];
+        for (level, threshold) in [lower, upper].iter().enumerate() {
+            let crossings: Vec<usize> = (0..vertices.len())
+                .filter(|i| matches!(vertices[*i].crossing, Some((l, _)) if l == level))
+                .collect();
+
+            let pairs = match crossings.len() {
+                2 => vec![(crossings[0], crossings[1])],
+                4 => {
+                    // Back from perimeter order to the order of CELL_OFFSETS
+                    let corners = [vals[0], vals[1], vals[3], vals[2]];
+                    let top_left_above = vals[0] >= *threshold;
+                    let centre_above = self.saddle.centre_above(corners, *threshold);
+
+                    // Crossings are ordered top, right, bottom, left
+                    // Cut off the top left and bottom right corners if they differ from the centre
+                    if top_left_above != centre_above {
+                        vec![(crossings[3], crossings[0]), (crossings[1], crossings[2])]
+                    } else {
+                        vec![(crossings[0], crossings[1]), (crossings[2], crossings[3])]
+                    }
+                }
+                _ => Vec::new(),
+            };
+
+            for (a, b) in pairs {
+                partners[a] = Some(b);
+                partners[b] = Some(a);
+            }
+        }
+
+        // Walk clockwise along the cell boundary while inside the band, and along a chord
+        // whenever the boundary leaves it, until arriving back at the start
+        let mut edges = Vec::new();
+        let mut visited = vec![false; vertices.len()];
+        for start in 0..vertices.len() {
+            if visited[start] || !matches!(vertices[start].crossing, Some((_, true))) {
+                continue;
+            }
+
+            let mut ring = Vec::new();
+            let mut i = start;
+            loop {
+                while !visited[i] {
+                    visited[i] = true;
+                    ring.push(vertices[i].point);
+                    if matches!(vertices[i].crossing, Some((_, false))) {
+                        break;
+                    }
+                    i = (i + 1) % vertices.len();
+                }
+
+                match partners[i] {
+                    Some(next) if next != start && !visited[next] => i = next,
+                    _ => break,
+                }
+            }
+            edges.extend(ring_edges(&ring));
+        }
+
+        edges
+    }
+
+    pub fn isoband(&self, lower: f32, upper: f32) -> IsobandLayer {
+        // Edges shared by two cells run in opposite directions and cancel out,
+        // leaving only the outlines of the band
+        let mut edges: HashSet<(Point<f32>, Point<f32>)> = HashSet::new();
+        for cell in self.pyramid.within_range(lower, upper) {
+            for (start, end) in self.cell_to_band_edges(&cell, lower, upper) {
+                if !edges.remove(&(end, start)) {
+                    edges.insert((start, end));
+                }
+            }
+        }
+
+        let mut paths = trace_rings(&edges);
+        canonical_order(&mut paths);
+        IsobandLayer {
+            lower,
+            upper,
+            paths,
+        }
+    }
+}
+
+// Cell corners in clockwise order, starting from the top left
+const PERIMETER: [CellIndex; 4] = [
+    CellIndex::TopLeft,
+    CellIndex::TopRight,
+    CellIndex::BottomRight,
+    CellIndex::BottomLeft,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct BandVertex {
+    point: Point<f32>,
+    // The threshold crossed (0 lower, 1 upper) and whether the boundary is entering the band
+    crossing: Option<(usize, bool)>,
+}
+
+fn band_level(val: f32, lower: f32, upper: f32) -> u8 {
+    if val < lower {
+        0
+    } else if val < upper {
+        1
+    } else {
+        2
+    }
+}
+
+fn band_vertices(
+    cells: &[Point<u32>],
+    vals: &[f32],
+    levels: &[u8],
+    lower: f32,
+    upper: f32,
+) -> Vec<BandVertex> {
+    let thresholds = [lower, upper];
+    let mut vertices = Vec::new();
+
+    for i in 0..PERIMETER.len() {
+        let j = (i + 1) % PERIMETER.len();
+        if levels[i] == 1 {
+            vertices.push(BandVertex {
+                point: Point {
+                    x: cells[i].x as f32,
+                    y: cells[i].y as f32,
+                },
+                crossing: None,
+            });
+        }
+
+        // Always interpolate top to bottom or left to right so neighbouring cells agree exactly
+        let (first, second) = if i < 2 { (i, j) } else { (j, i) };
+        let crossing_point = |threshold: f32| {
+            let t = dist_between_values(threshold, vals[first], vals[second]);
+            Point {
+                x: interpolate(t, cells[first].x, cells[second].x),
+                y: interpolate(t, cells[first].y, cells[second].y),
+            }
+        };
+
+        if levels[i] < levels[j] {
+            for level in levels[i]..levels[j] {
+                vertices.push(BandVertex {
+                    point: crossing_point(thresholds[level as usize]),
+                    crossing: Some((level as usize, level == 0)),
+                });
+            }
+        } else {
+            for level in (levels[j]..levels[i]).rev() {
+                vertices.push(BandVertex {
+                    point: crossing_point(thresholds[level as usize]),
+                    crossing: Some((level as usize, level == 1)),
+                });
+            }
+        }
+    }
+
+    vertices
+}
+
+fn cells_to_points(cells: &[Point<u32>]) -> Vec<Point<f32>> {
+    cells
+        .iter()
+        .map(|cell| Point {
+            x: cell.x as f32,
+            y: cell.y as f32,
+        })
+        .collect()
+}
+
+fn ring_edges(ring: &[Point<f32>]) -> Vec<(Point<f32>, Point<f32>)> {
+    (0..ring.len())
+        .map(|i| (ring[i], ring[(i + 1) % ring.len()]))
+        .filter(|(start, end)| start != end)
+        .collect()
+}
+
+fn trace_rings(edges: &HashSet<(Point<f32>, Point<f32>)>) -> Vec<Path> {
+    let mut next_points: HashMap<Point<f32>, Vec<Point<f32>>> = HashMap::new();
+    for (start, end) in edges {
+        next_points.entry(*start).or_default().push(*end);
+    }
+    // Where rings touch at a corner, which way they're joined mustn't depend on hash order
+    for ends in next_points.values_mut() {
+        ends.sort_by(compare_points);
+    }
+
+    let mut paths = Vec::new();
+    let mut starts: Vec<Point<f32>> = next_points.keys().copied().collect();
+    starts.sort_by(compare_points);
+    for start in starts {
+        while let Some(mut curr) = next_points.get_mut(&start).and_then(|ends| ends.pop()) {
+            let mut points = vec![start, curr];
+            while curr != start {
+                match next_points.get_mut(&curr).and_then(|ends| ends.pop()) {
+                    Some(next) => {
+                        points.push(next);
+                        curr = next;
+                    }
+                    None => break,
+                }
+            }
+
+            paths.push(Path {
+                circular: curr == start,
+                points,
+            });
+        }
+    }
+
+    paths
 }
 
 #[derive(Debug, PartialEq)]
@@ -371,25 +917,40 @@ enum NextSegmentError {
     OffImage,
 }
 
-fn get_next_segment<'a>(
-    cell_segments: &'a HashMap<Point<u32>, Vec<Segment>>,
-    segment: &Segment,
-) -> Result<&'a Segment, NextSegmentError> {
-    let cell_diff: Point<i32> = match segment.direction {
+impl Direction {
+    fn opposite(&self) -> Direction {
+        match self {
+            Direction::Up => Direction::Down,
+            Direction::Down => Direction::Up,
+            Direction::Left => Direction::Right,
+            Direction::Right => Direction::Left,
+        }
+    }
+}
+
+fn neighbour_coord(cell_coord: &Point<u32>, direction: Direction) -> Point<u32> {
+    let cell_diff: Point<i32> = match direction {
         Direction::Up => Point { x: 0, y: -1 },
         Direction::Down => Point { x: 0, y: 1 },
         Direction::Left => Point { x: -1, y: 0 },
         Direction::Right => Point { x: 1, y: 0 },
     };
 
-    let next_cell_coord: Point<u32> = Point {
-        x: (segment.cell_coord.x as i32 + cell_diff.x) as u32,
-        y: (segment.cell_coord.y as i32 + cell_diff.y) as u32,
-    };
+    Point {
+        x: (cell_coord.x as i32 + cell_diff.x) as u32,
+        y: (cell_coord.y as i32 + cell_diff.y) as u32,
+    }
+}
+
+fn get_next_segment<'a>(
+    cell_segments: &'a HashMap<Point<u32>, Vec<Segment>>,
+    segment: &Segment,
+) -> Result<&'a Segment, NextSegmentError> {
+    let next_cell_coord = neighbour_coord(&segment.cell_coord, segment.direction);
 
     if let Some(next_segments) = cell_segments.get(&next_cell_coord) {
         for seg in next_segments {
-            if segment.end == seg.start {
+            if segment.end.edge == seg.start.edge {
                 return Ok(seg);
             }
         }
@@ -400,15 +961,47 @@ fn get_next_segment<'a>(
     Err(NextSegmentError::SegmentMismatch)
 }
 
+fn get_previous_segment<'a>(
+    cell_segments: &'a HashMap<Point<u32>, Vec<Segment>>,
+    segment: &Segment,
+) -> Result<&'a Segment, NextSegmentError> {
+    // Segments only record the side they leave through, so look for a neighbour leaving
+    // towards this cell
+    let mut off_image = true;
+    for direction in &[
+        Direction::Up,
+        Direction::Down,
+        Direction::Left,
+        Direction::Right,
+    ] {
+        let prev_cell_coord = neighbour_coord(&segment.cell_coord, *direction);
+
+        if let Some(prev_segments) = cell_segments.get(&prev_cell_coord) {
+            off_image = false;
+            for seg in prev_segments {
+                if seg.direction == direction.opposite() && seg.end.edge == segment.start.edge {
+                    return Ok(seg);
+                }
+            }
+        }
+    }
+
+    if off_image {
+        Err(NextSegmentError::OffImage)
+    } else {
+        Err(NextSegmentError::SegmentMismatch)
+    }
+}
+
 fn trace_path<'a>(
     cell_segments: &'a HashMap<Point<u32>, Vec<Segment>>,
     visited_segments: &mut HashSet<Segment>,
     start_segment: &'a Segment,
-) -> Path {
-    let mut path_points = vec![start_segment.start.clone(), start_segment.end.clone()];
+) -> Fragment {
+    let mut path_points = vec![start_segment.start.point, start_segment.end.point];
 
-    let mut curr_segment = start_segment.clone();
-    visited_segments.insert(curr_segment.clone());
+    let mut curr_segment = *start_segment;
+    visited_segments.insert(curr_segment);
 
     let mut path_circular = false;
 
@@ -423,9 +1016,9 @@ fn trace_path<'a>(
                     break;
                 }
 
-                visited_segments.insert(next_segment.clone());
-                path_points.push(next_segment.end.clone());
-                curr_segment = next_segment.clone();
+                visited_segments.insert(*next_segment);
+                path_points.push(next_segment.end.point);
+                curr_segment = *next_segment;
             }
             Err(NextSegmentError::OffImage) => {
                 break;
@@ -436,28 +1029,212 @@ fn trace_path<'a>(
         }
     }
 
-    Path {
-        points: path_points,
-        circular: path_circular,
+    let last_segment = curr_segment;
+    let mut first_segment = *start_segment;
+
+    // The start segment may be part way along an open path, so follow it back to its true start
+    if !path_circular {
+        let mut preceding_points = Vec::new();
+        let mut curr_segment = *start_segment;
+
+        while let Ok(prev_segment) = get_previous_segment(cell_segments, &curr_segment) {
+            if visited_segments.contains(prev_segment) {
+                break;
+            }
+
+            visited_segments.insert(*prev_segment);
+            preceding_points.push(prev_segment.start.point);
+            curr_segment = *prev_segment;
+        }
+
+        preceding_points.reverse();
+        path_points.splice(0..0, preceding_points);
+        first_segment = curr_segment;
+    }
+
+    Fragment {
+        path: Path {
+            points: path_points,
+            circular: path_circular,
+        },
+        first: first_segment,
+        last: last_segment,
     }
 }
 
 fn trace_segments(cell_segments: &HashMap<Point<u32>, Vec<Segment>>) -> Vec<Path> {
-    let mut paths = Vec::new();
+    trace_fragments(cell_segments)
+        .into_iter()
+        .map(|fragment| fragment.path)
+        .collect()
+}
+
+fn trace_fragments(cell_segments: &HashMap<Point<u32>, Vec<Segment>>) -> Vec<Fragment> {
+    let mut fragments = Vec::new();
     let mut visited_segments: HashSet<Segment> = HashSet::new();
 
-    for (_, segments) in cell_segments {
+    for segments in cell_segments.values() {
         for segment in segments {
-            if visited_segments.contains(&segment) {
+            if visited_segments.contains(segment) {
                 continue;
             }
-            paths.push(trace_path(&cell_segments, &mut visited_segments, segment))
+            fragments.push(trace_path(cell_segments, &mut visited_segments, segment))
         }
     }
 
+    fragments
+}
+
+/// Join up paths traced separately in neighbouring chunks of the image
+///
+/// An open fragment continues into the fragment whose first segment would have been
+/// found by `get_next_segment` from its last one, had both been traced together.
+pub(crate) fn stitch_fragments(fragments: Vec<Fragment>) -> Vec<Path> {
+    let mut paths = Vec::new();
+    let mut open = Vec::new();
+    for fragment in fragments {
+        if fragment.path.circular {
+            paths.push(fragment.path);
+        } else {
+            open.push(fragment);
+        }
+    }
+
+    let starts: HashMap<(Point<u32>, GridEdge), usize> = open
+        .iter()
+        .enumerate()
+        .map(|(i, fragment)| ((fragment.first.cell_coord, fragment.first.start.edge), i))
+        .collect();
+    let next: Vec<Option<usize>> = open
+        .iter()
+        .map(|fragment| {
+            let next_cell = neighbour_coord(&fragment.last.cell_coord, fragment.last.direction);
+            starts.get(&(next_cell, fragment.last.end.edge)).copied()
+        })
+        .collect();
+
+    paths.extend(join_chains(
+        open.into_iter().map(|fragment| fragment.path).collect(),
+        &next,
+    ));
+    canonical_order(&mut paths);
     paths
 }
 
+/// Concatenate open paths that lead into one another, `next[i]` being the path continuing `i`
+///
+/// Chains with a true start become open paths, whatever is left over loops back on itself.
+pub fn join_chains(mut paths: Vec<Path>, next: &[Option<usize>]) -> Vec<Path> {
+    let mut has_previous = vec![false; paths.len()];
+    for i in next.iter().flatten() {
+        has_previous[*i] = true;
+    }
+
+    let mut joined = Vec::new();
+    let mut visited = vec![false; paths.len()];
+    let starts = (0..paths.len())
+        .filter(|i| !has_previous[*i])
+        .chain(0..paths.len())
+        .collect::<Vec<usize>>();
+    for start in starts {
+        if visited[start] {
+            continue;
+        }
+
+        let mut points = std::mem::take(&mut paths[start].points);
+        let mut circular = false;
+        visited[start] = true;
+        let mut curr = start;
+        while let Some(following) = next[curr] {
+            if visited[following] {
+                circular = following == start;
+                break;
+            }
+            visited[following] = true;
+            points.extend_from_slice(&paths[following].points[1..]);
+            curr = following;
+        }
+        joined.push(Path { points, circular });
+    }
+
+    joined
+}
+
+/// Put paths in the same order whatever order they were traced in
+///
+/// Closed paths are rotated to the start that puts their vertices first in row order,
+/// keeping their orientation, then paths are sorted by their vertices. Open paths are left as
+/// they are, their ends are where they meet the edge of the image or missing data.
+pub fn canonical_order(paths: &mut [Path]) {
+    for path in paths.iter_mut().filter(|path| path.circular) {
+        path.points.pop();
+        // A threshold equal to a sample can put the smallest vertex on the ring more than
+        // once, so every rotation starting on it is compared in full
+        let points = &path.points;
+        let rotation = |start: usize| points[start..].iter().chain(&points[..start]);
+        let start = match points.iter().min_by(|a, b| compare_points(a, b)) {
+            Some(smallest) => (0..points.len())
+                .filter(|i| points[*i] == *smallest)
+                .min_by(|a, b| compare_sequences(rotation(*a), rotation(*b)))
+                .unwrap_or(0),
+            None => 0,
+        };
+        path.points.rotate_left(start);
+        if let Some(first) = path.points.first().copied() {
+            path.points.push(first);
+        }
+    }
+
+    paths.sort_by(|a, b| {
+        compare_sequences(a.points.iter(), b.points.iter())
+            .then(a.points.len().cmp(&b.points.len()))
+            .then(a.circular.cmp(&b.circular))
+    });
+}
+
+// Row order of the first points that differ
+fn compare_sequences<'a>(
+    a: impl Iterator<Item = &'a Point<f32>>,
+    b: impl Iterator<Item = &'a Point<f32>>,
+) -> Ordering {
+    a.zip(b)
+        .map(|(a, b)| compare_points(a, b))
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+// Row order, top to bottom then left to right
+fn compare_points(a: &Point<f32>, b: &Point<f32>) -> Ordering {
+    a.y.total_cmp(&b.y).then(a.x.total_cmp(&b.x))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn map_parallel<T: Send, U: Send, F: Fn(T) -> U + Sync + Send>(
+    items: Vec<T>,
+    f: F,
+) -> Vec<U> {
+    items.into_par_iter().map(f).collect()
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn map_parallel<T, U, F: Fn(T) -> U>(items: Vec<T>, f: F) -> Vec<U> {
+    items.into_iter().map(f).collect()
+}
+
+// Roughly one chunk per thread, but not so thin that stitching dominates
+#[cfg(not(target_arch = "wasm32"))]
+fn chunk_rows(height: u32) -> u32 {
+    let cell_rows = height.saturating_sub(1).max(1);
+    cell_rows
+        .div_ceil(rayon::current_num_threads() as u32)
+        .max(MIN_CHUNK_ROWS)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn chunk_rows(height: u32) -> u32 {
+    height.saturating_sub(1).max(1)
+}
+
 fn interpolate<T: Integer + NumCast + Copy>(t: f32, left: T, right: T) -> f32 {
     let left: f32 = num::cast(left).unwrap();
     let right: f32 = num::cast(right).unwrap();
@@ -477,28 +1254,45 @@ fn dist_between_values(value: f32, start: f32, end: f32) -> f32 {
     (value - start) / length
 }
 
+// cell_state is a 4-bit number with one digit per corner in the order of offsets,
+// 1 when the corner is at or above the threshold and 0 when it's below or missing
+// cell_state = 0b<top_left><top_right><bottom_left><bottom_right>
+fn values_to_state(vals: &[Option<f32>; 4], threshold: f32) -> u8 {
+    vals.iter().fold(0, |cell_state, val| {
+        let corner_state = match val {
+            Some(val) if *val >= threshold => 1,
+            _ => 0,
+        };
+        (cell_state << 1) + corner_state
+    })
+}
+
+/// Smallest and largest corner value, with a missing corner counted as negative infinity as
+/// corners off the image are below every threshold
+fn values_range(vals: &[Option<f32>; 4]) -> (f32, f32) {
+    vals.iter()
+        .fold((f32::MAX, f32::MIN), |(lower, upper), val| match val {
+            Some(val) => (lower.min(*val), upper.max(*val)),
+            None => (f32::NEG_INFINITY, upper),
+        })
+}
+
 fn dist_between_option_values(
     value: f32,
     start: &Option<f32>,
     end: &Option<f32>,
 ) -> f32 {
-    if start.is_none() {
-        return 1.0;
-    }
-
-    if end.is_none() {
-        return 0.0;
+    match (start, end) {
+        (None, _) => 1.0,
+        (_, None) => 0.0,
+        (Some(start), Some(end)) => dist_between_values(value, *start, *end),
     }
-
-    let start = start.unwrap();
-    let end = end.unwrap();
-
-    dist_between_values(value, start, end)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::marching_triangles::MarchingTriangles;
     #[test]
     fn test_path() {
         assert_eq!(1, 1)
@@ -577,8 +1371,14 @@ mod tests {
         assert_eq!(
             marching_squares.cell_to_segments(&Point { x: 0, y: 0 }, 2.0),
             vec!(Segment {
-                start: Point { x: 0.0, y: 0.5 },
-                end: Point { x: 1.0, y: 0.0 },
+                start: Vertex {
+                    point: Point { x: 0.0, y: 0.5 },
+                    edge: GridEdge::Vertical(Point { x: 0, y: 0 }),
+                },
+                end: Vertex {
+                    point: Point { x: 1.0, y: 0.0 },
+                    edge: GridEdge::Horizontal(Point { x: 0, y: 0 }),
+                },
                 cell_coord: Point { x: 0, y: 0 },
                 direction: Direction::Up
             })
@@ -586,8 +1386,14 @@ mod tests {
         assert_eq!(
             marching_squares.cell_to_segments(&Point { x: 0, y: 0 }, 3.0),
             vec!(Segment {
-                start: Point { x: 0.0, y: 1.0 },
-                end: Point { x: 1.0, y: 0.5 },
+                start: Vertex {
+                    point: Point { x: 0.0, y: 1.0 },
+                    edge: GridEdge::Vertical(Point { x: 0, y: 0 }),
+                },
+                end: Vertex {
+                    point: Point { x: 1.0, y: 0.5 },
+                    edge: GridEdge::Vertical(Point { x: 1, y: 0 }),
+                },
                 cell_coord: Point { x: 0, y: 0 },
                 direction: Direction::Right
             })
@@ -595,8 +1401,14 @@ mod tests {
         assert_eq!(
             marching_squares.cell_to_segments(&Point { x: 0, y: 0 }, 4.0),
             vec!(Segment {
-                start: Point { x: 1.0, y: 1.0 },
-                end: Point { x: 1.0, y: 1.0 },
+                start: Vertex {
+                    point: Point { x: 1.0, y: 1.0 },
+                    edge: GridEdge::Horizontal(Point { x: 0, y: 1 }),
+                },
+                end: Vertex {
+                    point: Point { x: 1.0, y: 1.0 },
+                    edge: GridEdge::Vertical(Point { x: 1, y: 0 }),
+                },
                 cell_coord: Point { x: 0, y: 0 },
                 direction: Direction::Right
             })
@@ -714,7 +1526,7 @@ mod tests {
         let segments = marching_squares.segments_for_threshold(7.0);
 
         assert_eq!(
-            trace_path(&segments, &mut HashSet::new(), &segments[&Point { x: 2, y: 2 }][0]),
+            trace_path(&segments, &mut HashSet::new(), &segments[&Point { x: 2, y: 2 }][0]).path,
             Path { points: vec![
                 Point { x: 2.5, y: 3.0 },
                 Point { x: 3.0, y: 2.5 },
@@ -767,4 +1579,551 @@ mod tests {
             assert!(path.points.contains(&point));
         }
     }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_previous_segment() {
+        let data = [
+            1, 2, 3, 4, 4, 3, 2, 1, 
+            2, 3, 4, 5, 5, 4, 3, 2, 
+            3, 4, 5, 6, 6, 5, 4, 3, 
+            4, 5, 6, 8, 8, 6, 5, 4, 
+            4, 5, 6, 8, 8, 6, 5, 4, 
+            3, 4, 5, 6, 6, 5, 4, 3, 
+            2, 3, 4, 5, 5, 4, 3, 2, 
+            1, 2, 3, 4, 4, 3, 2, 1,
+        ].iter().map(|num| *num as f32).collect::<Vec<f32>>();
+        let img = Image::new(data, 8, 8);
+        let marching_squares = MarchingSquares::new(&img);
+
+        let segments = marching_squares.segments_for_threshold(7.0);
+
+        let segment = &segments[&Point { x: 3, y: 2 }][0];
+        let expected: Result<&Segment, NextSegmentError> = Ok(&segments[&Point { x: 2, y: 2 }][0]);
+        assert_eq!(get_previous_segment(&segments, segment), expected);
+
+        let segment = &segments[&Point { x: 2, y: 2 }][0];
+        let expected: Result<&Segment, NextSegmentError> = Ok(&segments[&Point { x: 2, y: 3 }][0]);
+        assert_eq!(get_previous_segment(&segments, segment), expected);
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_trace_open_paths() {
+        // Two ridge edges running from the top of the image to the bottom
+        let data = [
+            1, 1, 5, 5, 5, 1, 1, 1, 
+            1, 1, 5, 5, 5, 1, 1, 1, 
+            1, 1, 5, 5, 5, 1, 1, 1, 
+            1, 1, 5, 5, 5, 1, 1, 1, 
+            1, 1, 5, 5, 5, 1, 1, 1, 
+            1, 1, 5, 5, 5, 1, 1, 1,
+        ].iter().map(|num| *num as f32).collect::<Vec<f32>>();
+        let img = Image::new(data, 8, 6);
+        let marching_squares = MarchingSquares::new(&img);
+
+        // Segment maps are iterated in a different order every time, so trace a few of them
+        for _ in 0..20 {
+            let paths = marching_squares.isoline(3.0).paths;
+            assert_eq!(paths.len(), 2);
+            for path in &paths {
+                assert!(!path.circular);
+                assert_eq!(path.points.len(), 6);
+                assert!(path.points.iter().any(|point| point.y == 0.0));
+                assert!(path.points.iter().any(|point| point.y == 5.0));
+            }
+        }
+
+        // A single diagonal line across a slope
+        let data = (0..8)
+            .flat_map(|y| (0..8).map(move |x| (x + y) as f32))
+            .collect::<Vec<f32>>();
+        let img = Image::new(data, 8, 8);
+        let marching_squares = MarchingSquares::new(&img);
+
+        for _ in 0..20 {
+            let paths = marching_squares.isoline(6.5).paths;
+            assert_eq!(paths.len(), 1);
+            assert!(!paths[0].circular);
+            assert_eq!(paths[0].points.len(), 14);
+        }
+
+        // The corners of four low blocks split up by a cross shaped ridge
+        let data = [
+            1, 1, 1, 5, 1, 1, 1, 
+            1, 1, 1, 5, 1, 1, 1, 
+            1, 1, 1, 5, 1, 1, 1, 
+            5, 5, 5, 5, 5, 5, 5, 
+            1, 1, 1, 5, 1, 1, 1, 
+            1, 1, 1, 5, 1, 1, 1, 
+            1, 1, 1, 5, 1, 1, 1,
+        ].iter().map(|num| *num as f32).collect::<Vec<f32>>();
+        let img = Image::new(data, 7, 7);
+        let marching_squares = MarchingSquares::new(&img);
+
+        for _ in 0..20 {
+            let paths = marching_squares.isoline(3.0).paths;
+            assert_eq!(paths.len(), 4);
+            for path in &paths {
+                assert!(!path.circular);
+                assert_eq!(path.points.len(), 6);
+            }
+        }
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_missing_values() {
+        let data = [
+            1, 2, 3, 4, 4, 3, 2, 1, 
+            2, 3, 4, 5, 5, 4, 3, 2, 
+            3, 4, 5, 6, 6, 5, 4, 3, 
+            4, 5, 6, 8, 8, -32768, 5, 4, 
+            4, 5, 6, 8, 8, 6, 5, 4, 
+            3, 4, 5, 6, 6, 5, 4, 3, 
+            2, 3, 4, 5, 5, 4, 3, 2, 
+            1, 2, 3, 4, 4, 3, 2, 1,
+        ].iter().map(|num| *num as f32).collect::<Vec<f32>>();
+
+        let mut nan_data = data.clone();
+        nan_data[3 * 8 + 5] = f32::NAN;
+
+        for img in [
+            Image::new(data, 8, 8).with_nodata(-32768.0),
+            Image::new(nan_data, 8, 8),
+        ] {
+            let marching_squares = MarchingSquares::new(&img);
+
+            assert_eq!(marching_squares.cell_to_segments(&Point { x: 4, y: 2 }, 7.0), vec![]);
+            assert_eq!(marching_squares.cell_to_segments(&Point { x: 5, y: 3 }, 7.0), vec![]);
+
+            // The ring around the peak is left open where it meets the missing sample
+            let paths = marching_squares.isoline(7.0).paths;
+            assert_eq!(paths.len(), 1);
+            assert!(!paths[0].circular);
+            assert_eq!(paths[0].points.len(), 7);
+
+            // Bands are closed off around the missing cells
+            let band = marching_squares.isoband(5.0, 7.0);
+            assert!(!band.paths.is_empty());
+            assert!(band.paths.iter().all(|path| path.circular));
+        }
+    }
+
+    #[test]
+    fn test_image_border() {
+        #[rustfmt::skip]
+        let data = vec![
+            0.0, 0.0, 0.0, 0.0,
+            0.0, 9.0, 9.0, 9.0,
+            0.0, 9.0, 9.0, 9.0,
+            0.0, 9.0, 0.0, 9.0,
+        ];
+        let img = Image::new(data, 4, 4);
+        let marching_squares = MarchingSquares::new(&img);
+        let points = |x, y| -> Vec<(Point<f32>, Point<f32>)> {
+            marching_squares
+                .cell_to_segments(&Point { x, y }, 4.5)
+                .iter()
+                .map(|segment| (segment.start.point, segment.end.point))
+                .collect()
+        };
+        let point = |x, y| Point { x, y };
+
+        // Corners off the image are below the threshold, as they always have been, so cells
+        // along the right and bottom close the lines off along the edge
+        assert_eq!(points(3, 0), vec![(point(3.0, 0.5), point(3.0, 1.0))]);
+        assert_eq!(points(3, 1), vec![(point(3.0, 1.0), point(3.0, 2.0))]);
+        assert_eq!(points(0, 3), vec![(point(1.0, 3.0), point(0.5, 3.0))]);
+        assert_eq!(points(1, 3), vec![(point(1.5, 3.0), point(1.0, 3.0))]);
+
+        // Missing samples inside the image still leave lines open
+        let masked = Image::new(img.into_data(), 4, 4)
+            .with_mask((0..16).map(|i| i != 7).collect())
+            .unwrap();
+        let marching_squares = MarchingSquares::new(&masked);
+        assert_eq!(
+            marching_squares.cell_to_segments(&Point { x: 3, y: 1 }, 4.5),
+            vec![]
+        );
+        assert_eq!(
+            marching_squares.cell_to_segments(&Point { x: 2, y: 1 }, 4.5),
+            vec![]
+        );
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_band_state() {
+        let data = [
+            1, 2, 5, 6, 2, 2, 2, 2, 
+            3, 4, 7, 8, 2, 2, 2, 2, 
+            3, 3, 3, 3, 4, 4, 4, 4, 
+            3, 3, 3, 3, 4, 4, 4, 4,
+        ].iter().map(|num| *num as f32).collect::<Vec<f32>>();
+        let img = Image::new(data, 8, 4);
+        let marching_squares = MarchingSquares::new(&img);
+
+        // 1 2 / 3 4 against [2, 4) is 0 1 / 1 2 in base 3
+        assert_eq!(marching_squares.band_state(&Point { x: 0, y: 0 }, 2.0, 4.0), Some(14));
+        assert_eq!(marching_squares.band_state(&Point { x: 0, y: 0 }, 0.0, 1.0), Some(80));
+        assert_eq!(marching_squares.band_state(&Point { x: 0, y: 0 }, 5.0, 6.0), Some(0));
+        assert_eq!(marching_squares.band_state(&Point { x: 0, y: 0 }, 1.0, 5.0), Some(40));
+        assert_eq!(marching_squares.band_state(&Point { x: 7, y: 0 }, 1.0, 5.0), None);
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_cell_to_band_edges() {
+        let data = [
+            1, 2, 5, 6, 2, 2, 2, 2, 
+            3, 4, 7, 8, 2, 2, 2, 2, 
+            3, 3, 3, 3, 4, 4, 4, 4, 
+            3, 3, 3, 3, 4, 4, 4, 4,
+        ].iter().map(|num| *num as f32).collect::<Vec<f32>>();
+        let img = Image::new(data, 8, 4);
+        let marching_squares = MarchingSquares::new(&img);
+
+        assert_eq!(
+            marching_squares.cell_to_band_edges(&Point { x: 0, y: 0 }, 1.5, 3.5),
+            vec![
+                (Point { x: 0.5, y: 0.0 }, Point { x: 1.0, y: 0.0 }),
+                (Point { x: 1.0, y: 0.0 }, Point { x: 1.0, y: 0.75 }),
+                (Point { x: 1.0, y: 0.75 }, Point { x: 0.5, y: 1.0 }),
+                (Point { x: 0.5, y: 1.0 }, Point { x: 0.0, y: 1.0 }),
+                (Point { x: 0.0, y: 1.0 }, Point { x: 0.0, y: 0.25 }),
+                (Point { x: 0.0, y: 0.25 }, Point { x: 0.5, y: 0.0 }),
+            ]
+        );
+        // Thresholds landing exactly on corners don't leave zero length edges behind
+        assert_eq!(
+            marching_squares.cell_to_band_edges(&Point { x: 0, y: 0 }, 2.0, 4.0),
+            vec![
+                (Point { x: 1.0, y: 0.0 }, Point { x: 1.0, y: 1.0 }),
+                (Point { x: 1.0, y: 1.0 }, Point { x: 0.0, y: 1.0 }),
+                (Point { x: 0.0, y: 1.0 }, Point { x: 0.0, y: 0.5 }),
+                (Point { x: 0.0, y: 0.5 }, Point { x: 1.0, y: 0.0 }),
+            ]
+        );
+        assert_eq!(
+            marching_squares.cell_to_band_edges(&Point { x: 0, y: 0 }, 5.0, 6.0),
+            vec![]
+        );
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_isoband() {
+        let data = [
+            1, 2, 3, 4, 4, 3, 2, 1, 
+            2, 3, 4, 5, 5, 4, 3, 2, 
+            3, 4, 5, 6, 6, 5, 4, 3, 
+            4, 5, 6, 8, 8, 6, 5, 4, 
+            4, 5, 6, 8, 8, 6, 5, 4, 
+            3, 4, 5, 6, 6, 5, 4, 3, 
+            2, 3, 4, 5, 5, 4, 3, 2, 
+            1, 2, 3, 4, 4, 3, 2, 1,
+        ].iter().map(|num| *num as f32).collect::<Vec<f32>>();
+        let img = Image::new(data, 8, 8);
+        let marching_squares = MarchingSquares::new(&img);
+
+        // A ring with the peak cut out as a hole
+        let band = marching_squares.isoband(5.0, 7.0);
+        assert_eq!(band.paths.len(), 2);
+        assert!(band.paths.iter().all(|path| path.circular));
+
+        let hole = band.paths.iter().find(|path| path.points.len() == 9).unwrap();
+        for point in [
+            Point { x: 2.5, y: 3.0 },
+            Point { x: 3.0, y: 2.5 },
+            Point { x: 4.0, y: 2.5 },
+            Point { x: 4.5, y: 3.0 },
+            Point { x: 4.5, y: 4.0 },
+            Point { x: 4.0, y: 4.5 },
+            Point { x: 3.0, y: 4.5 },
+            Point { x: 2.5, y: 4.0 },
+        ] {
+            assert!(hole.points.contains(&point));
+        }
+
+        let polygons = band.polygons();
+        assert_eq!(polygons.len(), 1);
+        assert_eq!(polygons[0].holes, vec![hole.clone()]);
+
+        // The low corners are closed off along the edge of the image
+        let band = marching_squares.isoband(0.0, 3.0);
+        assert_eq!(band.paths.len(), 4);
+        assert!(band.paths.iter().all(|path| path.circular));
+
+        // Every value is inside the band, leaving only the outline of the image
+        let band = marching_squares.isoband(0.0, 10.0);
+        assert_eq!(band.paths.len(), 1);
+        let outline = &band.paths[0];
+        assert!(outline.circular);
+        assert_eq!(outline.points.len(), 29);
+        assert!(outline.points.contains(&Point { x: 0.0, y: 0.0 }));
+        assert!(outline.points.contains(&Point { x: 7.0, y: 7.0 }));
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_isoline_polygons() {
+        let data = [
+            0, 0, 0, 0, 0, 0, 0,
+            0, 5, 5, 5, 5, 5, 0,
+            0, 5, 0, 0, 0, 5, 0,
+            0, 5, 0, 9, 0, 5, 0,
+            0, 5, 0, 0, 0, 5, 0,
+            0, 5, 5, 5, 5, 5, 0,
+            0, 0, 0, 0, 0, 0, 0,
+        ].iter().map(|num| *num as f32).collect::<Vec<f32>>();
+        let img = Image::new(data, 7, 7);
+        let layer = MarchingSquares::new(&img).isoline(2.5);
+        assert_eq!(layer.paths.len(), 3);
+
+        // The square ring with its hollow as a hole, and the peak inside the hollow on its own
+        let mut polygons = layer.polygons();
+        polygons.sort_by_key(|polygon| polygon.holes.len());
+        assert_eq!(polygons.len(), 2);
+        assert!(polygons[0].holes.is_empty());
+        assert_eq!(polygons[1].holes.len(), 1);
+        let peak = &polygons[0].exterior;
+        assert!(peak.points.iter().all(|point| (2.0..4.0).contains(&point.x)));
+
+        // Exteriors wind counter-clockwise and holes clockwise, with the y axis pointing up
+        for polygon in &polygons {
+            assert!(signed_area(&polygon.exterior) > 0.0);
+            assert!(polygon.holes.iter().all(|hole| signed_area(hole) < 0.0));
+        }
+    }
+
+    #[test]
+    fn test_saddle_strategy() {
+        // Both saddles have a centre average above 0.5 but a bilinear saddle value below it
+        let rising = Image::new(vec![0.0, 4.0, 0.55, 0.0], 2, 2);
+        let falling = Image::new(vec![4.0, 0.0, 0.0, 0.55], 2, 2);
+
+        // Whether a path runs from the left edge of the cell to the top edge
+        let cuts_top_left = |paths: &[Path]| {
+            paths.iter().any(|path| {
+                path.points.iter().any(|point| point.x == 0.0)
+                    && path.points.iter().any(|point| point.y == 0.0)
+            })
+        };
+
+        for (saddle, high_joined) in [
+            (SaddleStrategy::CentreAverage, true),
+            (SaddleStrategy::AsymptoticDecider, false),
+            (SaddleStrategy::JoinHigh, true),
+            (SaddleStrategy::JoinLow, false),
+        ] {
+            let layer = MarchingSquares::new(&rising)
+                .with_saddle_strategy(saddle)
+                .isoline(0.5);
+            assert_eq!(layer.paths.len(), 2);
+            assert_eq!(cuts_top_left(&layer.paths), high_joined, "{:?}", saddle);
+
+            let layer = MarchingSquares::new(&falling)
+                .with_saddle_strategy(saddle)
+                .isoline(0.5);
+            assert_eq!(layer.paths.len(), 2);
+            assert_eq!(cuts_top_left(&layer.paths), !high_joined, "{:?}", saddle);
+
+            // Joined high corners make one band through the cell, otherwise each is cut off
+            let band = MarchingSquares::new(&rising)
+                .with_saddle_strategy(saddle)
+                .isoband(0.5, 10.0);
+            let expected = if high_joined { 1 } else { 2 };
+            assert_eq!(band.paths.len(), expected, "{:?}", saddle);
+        }
+    }
+
+    #[test]
+    fn test_isolines() {
+        // Peaks, a pit and a few missing samples
+        let (width, height) = (17, 11);
+        let data: Vec<f32> = (0..width * height)
+            .map(|i| {
+                let (x, y) = ((i % width) as f32, (i / width) as f32);
+                if i % 23 == 7 {
+                    f32::NAN
+                } else {
+                    (x * 0.6).sin() * 4.0 + (y * 0.5).cos() * 3.0
+                }
+            })
+            .collect();
+        let img = Image::new(data, width, height);
+        let marching_squares = MarchingSquares::new(&img);
+
+        // Unsorted and repeated thresholds, one on a sample value and some outside the data
+        let thresholds = [2.0, -1.5, 0.0, 4.0, 2.0, -10.0, 3.0, 10.0, -4.5];
+        let layers = marching_squares.isolines(&thresholds);
+        assert_eq!(layers.len(), thresholds.len());
+        for (layer, threshold) in layers.iter().zip(&thresholds) {
+            assert_eq!(layer.threshold, *threshold);
+            assert_eq!(layer, &marching_squares.isoline(*threshold));
+        }
+        assert!(layers[5].paths.is_empty());
+        assert!(!layers[0].paths.is_empty());
+
+        assert!(marching_squares.isolines(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_isolines_in_chunks() {
+        let (width, height) = (29, 41);
+        let data: Vec<f32> = (0..width * height)
+            .map(|i| {
+                let (x, y) = ((i % width) as f32, (i / width) as f32);
+                if i % 37 == 11 {
+                    f32::NAN
+                } else {
+                    (x * 0.45).sin() * (y * 0.3).cos() * 5.0 + y * 0.05
+                }
+            })
+            .collect();
+        let img = Image::new(data, width, height);
+        let marching_squares = MarchingSquares::new(&img);
+
+        let thresholds = [-2.0, 0.0, 1.0, 3.5];
+        let serial: Vec<IsolineLayer> = thresholds
+            .iter()
+            .map(|threshold| marching_squares.isoline(*threshold))
+            .collect();
+        assert!(serial.iter().all(|layer| layer.paths.len() > 3));
+
+        for rows in [1, 2, 3, 7, 40, 64] {
+            assert_eq!(
+                marching_squares.isolines_in_chunks(&thresholds, rows),
+                serial,
+                "chunks of {} rows",
+                rows
+            );
+        }
+    }
+
+    #[test]
+    fn test_with_origin() {
+        #[rustfmt::skip]
+        let data = vec![
+            0.0, 0.0, 0.0,
+            0.0, 4.0, 0.0,
+            0.0, 0.0, 0.0,
+        ];
+        let img = Image::new(data, 3, 3);
+        let origin = Point { x: 10, y: 20 };
+        let shifted = MarchingSquares::new(&img).with_origin(origin);
+        let marching_squares = MarchingSquares::new(&img);
+
+        let expected: Vec<Point<f32>> = marching_squares.isoline(2.0).paths[0]
+            .points
+            .iter()
+            .map(|point| Point {
+                x: point.x + 10.0,
+                y: point.y + 20.0,
+            })
+            .collect();
+        let points = &shifted.isoline(2.0).paths[0].points;
+        assert_eq!(points.len(), expected.len());
+        assert!(expected.iter().all(|point| points.contains(point)));
+
+        let band = shifted.isoband(2.0, 5.0);
+        assert_eq!(band.paths.len(), 1);
+        assert!(band.paths[0].points.contains(&Point { x: 11.0, y: 20.5 }));
+    }
+
+    #[test]
+    fn test_canonical_order() {
+        let ring = |points: &[(f32, f32)]| Path {
+            points: points.iter().map(|(x, y)| Point { x: *x, y: *y }).collect(),
+            circular: true,
+        };
+        let mut paths = vec![
+            ring(&[(2.0, 1.5), (1.5, 2.0), (1.0, 1.5), (1.5, 1.0), (2.0, 1.5)]),
+            Path {
+                points: vec![Point { x: 3.0, y: 0.5 }, Point { x: 0.0, y: 0.5 }],
+                circular: false,
+            },
+        ];
+        canonical_order(&mut paths);
+
+        assert_eq!(
+            paths[0].points,
+            vec![Point { x: 3.0, y: 0.5 }, Point { x: 0.0, y: 0.5 }]
+        );
+        assert_eq!(
+            paths[1],
+            ring(&[(1.5, 1.0), (2.0, 1.5), (1.5, 2.0), (1.0, 1.5), (1.5, 1.0)])
+        );
+    }
+
+    #[test]
+    fn test_deterministic() {
+        // Saddles and rings touching at a corner, where tracing order could make a difference
+        let (width, height) = (19, 15);
+        let data: Vec<f32> = (0..width * height)
+            .map(|i| {
+                let (x, y) = ((i % width) as f32, (i / width) as f32);
+                ((x * 0.9).sin() * (y * 0.8).cos() * 4.0).round()
+            })
+            .collect();
+        let img = Image::new(data, width, height);
+
+        let first = MarchingSquares::new(&img);
+        let isolines = first.isolines(&[-1.0, 0.5, 2.0]);
+        let isoband = first.isoband(-1.0, 2.0);
+        for _ in 0..5 {
+            let marching_squares = MarchingSquares::new(&img);
+            assert_eq!(marching_squares.isolines(&[-1.0, 0.5, 2.0]), isolines);
+            assert_eq!(marching_squares.isoband(-1.0, 2.0), isoband);
+        }
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_deterministic_at_sample_values() {
+        // A threshold equal to a sample puts the same vertex on a ring more than once, and
+        // where the ring starts mustn't depend on which copy tracing reached first
+        let data = [
+            1, 2, 3, 4, 4, 3, 2, 1,
+            2, 3, 4, 5, 5, 4, 3, 2,
+            3, 4, 5, 6, 6, 5, 4, 3,
+            4, 5, 6, 8, 8, 6, 5, 4,
+            4, 5, 6, 8, 8, 6, 5, 4,
+            3, 4, 5, 6, 6, 5, 4, 3,
+            2, 3, 4, 5, 5, 4, 3, 2,
+            1, 2, 3, 4, 4, 3, 2, 1,
+        ].iter().map(|num| *num as f32).collect::<Vec<f32>>();
+        let img = Image::new(data, 8, 8);
+        let thresholds = [3.0, 4.0, 5.0, 6.0];
+
+        let isolines = MarchingSquares::new(&img).isolines(&thresholds);
+        let triangles = MarchingTriangles::new(&img).isolines(&thresholds);
+        for _ in 0..20 {
+            let marching_squares = MarchingSquares::new(&img);
+            assert_eq!(marching_squares.isolines(&thresholds), isolines);
+            assert_eq!(marching_squares.isolines_in_chunks(&thresholds, 3), isolines);
+            assert_eq!(MarchingTriangles::new(&img).isolines(&thresholds), triangles);
+        }
+    }
+
+    #[test]
+    fn test_shared_vertices() {
+        // A threshold a fifth of the way between samples isn't exact in f32, and used to
+        // land on slightly different points from the cells either side of an edge
+        #[rustfmt::skip]
+        let data = vec![
+            0.0, 0.0, 0.0, 0.0,
+            5.0, 5.0, 5.0, 5.0,
+        ];
+        let img = Image::new(data, 4, 2);
+        let isoline = MarchingSquares::new(&img).isoline(2.0);
+
+        assert_eq!(isoline.paths.len(), 1);
+        assert_eq!(isoline.paths[0].points.len(), 4);
+        assert!(isoline.paths[0]
+            .points
+            .windows(2)
+            .all(|pair| pair[0].y == pair[1].y));
+    }
 }
@@ -0,0 +1,344 @@
+use super::marching_squares::{canonical_order, map_parallel, IsolineLayer, Path};
+use super::pyramid::*;
+use super::util::*;
+use std::collections::HashMap;
+
+/// Which diagonal each cell is cut along to make two triangles
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Diagonal {
+    /// Always from the top left corner to the bottom right
+    #[default]
+    Fixed,
+    /// Along whichever diagonal joins the closer pair of values, or around a single missing
+    /// corner so the rest of the cell is still contoured
+    DataDriven,
+}
+
+/// Contours traced through triangles rather than squares
+///
+/// Values are linear across a triangle, so every triangle is crossed by at most one segment
+/// at each threshold and the result never depends on how a saddle is resolved.
+pub struct MarchingTriangles<'a> {
+    img: &'a Image<f32>,
+    pyramid: MinMaxPyramid,
+    diagonal: Diagonal,
+}
+
+/// The line between two neighbouring samples, top or left one first
+type SampleEdge = (Point<u32>, Point<u32>);
+
+/// Where an isoline crosses one triangle, oriented like the segments of marching squares
+struct TriangleSegment {
+    start: (SampleEdge, Point<f32>),
+    end: (SampleEdge, Point<f32>),
+}
+
+const TOP_LEFT: Point<u32> = Point { x: 0, y: 0 };
+const TOP_RIGHT: Point<u32> = Point { x: 1, y: 0 };
+const BOTTOM_LEFT: Point<u32> = Point { x: 0, y: 1 };
+const BOTTOM_RIGHT: Point<u32> = Point { x: 1, y: 1 };
+
+impl<'a> MarchingTriangles<'a> {
+    pub fn new(img: &'a Image<f32>) -> MarchingTriangles<'a> {
+        MarchingTriangles {
+            img,
+            pyramid: MinMaxPyramid::create(img),
+            diagonal: Diagonal::default(),
+        }
+    }
+
+    pub fn with_diagonal(mut self, diagonal: Diagonal) -> Self {
+        self.diagonal = diagonal;
+        self
+    }
+
+    pub fn image(&self) -> &Image<f32> {
+        self.img
+    }
+
+    pub fn isoline(&self, threshold: f32) -> IsolineLayer {
+        let segments: Vec<TriangleSegment> = self
+            .pyramid
+            .crossing(threshold)
+            .iter()
+            .flat_map(|cell| self.cell_to_segments(cell, threshold))
+            .collect();
+        let mut paths = trace_triangle_segments(&segments);
+        canonical_order(&mut paths);
+        IsolineLayer { threshold, paths }
+    }
+
+    /// One layer per threshold, in the order given
+    pub fn isolines(&self, thresholds: &[f32]) -> Vec<IsolineLayer> {
+        map_parallel(thresholds.to_vec(), |threshold| self.isoline(threshold))
+    }
+
+    fn cell_to_segments(&self, cell: &Point<u32>, threshold: f32) -> Vec<TriangleSegment> {
+        self.triangles(cell)
+            .iter()
+            .filter_map(|triangle| self.triangle_to_segment(triangle, threshold))
+            .collect()
+    }
+
+    /// The two triangles of a cell, each with its corners in clockwise order
+    fn triangles(&self, cell: &Point<u32>) -> [[Point<u32>; 3]; 2] {
+        let corner = |offset: Point<u32>| cell + offset;
+        let top_left_to_bottom_right = match self.diagonal {
+            Diagonal::Fixed => true,
+            Diagonal::DataDriven => {
+                let val = |offset| self.img.get_val(&corner(offset));
+                match (
+                    val(TOP_LEFT),
+                    val(TOP_RIGHT),
+                    val(BOTTOM_LEFT),
+                    val(BOTTOM_RIGHT),
+                ) {
+                    (Some(tl), Some(tr), Some(bl), Some(br)) => (tl - br).abs() <= (tr - bl).abs(),
+                    (None, ..) | (.., None) => false,
+                    _ => true,
+                }
+            }
+        };
+
+        if top_left_to_bottom_right {
+            [
+                [corner(TOP_LEFT), corner(TOP_RIGHT), corner(BOTTOM_RIGHT)],
+                [corner(TOP_LEFT), corner(BOTTOM_RIGHT), corner(BOTTOM_LEFT)],
+            ]
+        } else {
+            [
+                [corner(TOP_LEFT), corner(TOP_RIGHT), corner(BOTTOM_LEFT)],
+                [corner(TOP_RIGHT), corner(BOTTOM_RIGHT), corner(BOTTOM_LEFT)],
+            ]
+        }
+    }
+
+    fn triangle_to_segment(
+        &self,
+        triangle: &[Point<u32>; 3],
+        threshold: f32,
+    ) -> Option<TriangleSegment> {
+        let mut vals = [0.0; 3];
+        for (val, corner) in vals.iter_mut().zip(triangle) {
+            *val = self.img.get_val(corner)?;
+        }
+
+        // Going clockwise, the isoline enters where the corners drop below the threshold and
+        // leaves where they rise above it again, so neighbours agree on which way it runs
+        let (mut start, mut end) = (None, None);
+        for i in 0..3 {
+            let j = (i + 1) % 3;
+            match (vals[i] >= threshold, vals[j] >= threshold) {
+                (true, false) => {
+                    start = Some(crossing(
+                        triangle[i],
+                        triangle[j],
+                        vals[i],
+                        vals[j],
+                        threshold,
+                    ))
+                }
+                (false, true) => {
+                    end = Some(crossing(
+                        triangle[i],
+                        triangle[j],
+                        vals[i],
+                        vals[j],
+                        threshold,
+                    ))
+                }
+                _ => {}
+            }
+        }
+
+        Some(TriangleSegment {
+            start: start?,
+            end: end?,
+        })
+    }
+}
+
+/// Where `threshold` falls between two samples, always interpolated from the top or left one
+/// so the triangles either side of an edge place the vertex at exactly the same point
+fn crossing(
+    a: Point<u32>,
+    b: Point<u32>,
+    a_val: f32,
+    b_val: f32,
+    threshold: f32,
+) -> (SampleEdge, Point<f32>) {
+    let ((from, from_val), (to, to_val)) = if (a.y, a.x) <= (b.y, b.x) {
+        ((a, a_val), (b, b_val))
+    } else {
+        ((b, b_val), (a, a_val))
+    };
+
+    let t = if threshold == from_val {
+        0.0
+    } else {
+        (threshold - from_val) / (to_val - from_val)
+    };
+    let point = Point {
+        x: from.x as f32 + (to.x as f32 - from.x as f32) * t,
+        y: from.y as f32 + (to.y as f32 - from.y as f32) * t,
+    };
+    ((from, to), point)
+}
+
+fn trace_triangle_segments(segments: &[TriangleSegment]) -> Vec<Path> {
+    let starts: HashMap<SampleEdge, usize> = segments
+        .iter()
+        .enumerate()
+        .map(|(i, segment)| (segment.start.0, i))
+        .collect();
+    let mut has_previous = vec![false; segments.len()];
+    for segment in segments {
+        if let Some(next) = starts.get(&segment.end.0) {
+            has_previous[*next] = true;
+        }
+    }
+
+    // Open paths start where nothing leads in, whatever is left over is a ring
+    let mut paths = Vec::new();
+    let mut visited = vec![false; segments.len()];
+    let first_segments = (0..segments.len())
+        .filter(|i| !has_previous[*i])
+        .chain(0..segments.len())
+        .collect::<Vec<usize>>();
+    for first in first_segments {
+        if visited[first] {
+            continue;
+        }
+
+        let mut points = vec![segments[first].start.1];
+        let mut i = first;
+        let mut circular = false;
+        loop {
+            visited[i] = true;
+            points.push(segments[i].end.1);
+            match starts.get(&segments[i].end.0) {
+                Some(next) if *next == first => {
+                    circular = true;
+                    break;
+                }
+                Some(next) if !visited[*next] => i = *next,
+                _ => break,
+            }
+        }
+        paths.push(Path { points, circular });
+    }
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MarchingSquares;
+
+    fn peak() -> Image<f32> {
+        #[rustfmt::skip]
+        let data = [
+            1, 2, 3, 4, 4, 3, 2, 1,
+            2, 3, 4, 5, 5, 4, 3, 2,
+            3, 4, 5, 6, 6, 5, 4, 3,
+            4, 5, 6, 8, 8, 6, 5, 4,
+            4, 5, 6, 8, 8, 6, 5, 4,
+            3, 4, 5, 6, 6, 5, 4, 3,
+            2, 3, 4, 5, 5, 4, 3, 2,
+            1, 2, 3, 4, 4, 3, 2, 1,
+        ].iter().map(|num| *num as f32).collect::<Vec<f32>>();
+        Image::new(data, 8, 8)
+    }
+
+    #[test]
+    fn test_isoline() {
+        let img = peak();
+        for diagonal in [Diagonal::Fixed, Diagonal::DataDriven] {
+            let triangles = MarchingTriangles::new(&img).with_diagonal(diagonal);
+
+            // One ring around the peak, closed and starting from its top left point
+            let layer = triangles.isoline(5.5);
+            assert_eq!(layer.paths.len(), 1, "{:?}", diagonal);
+            let ring = &layer.paths[0];
+            assert!(ring.circular);
+            assert_eq!(ring.points.first(), ring.points.last());
+            assert!(ring.points.contains(&Point { x: 3.0, y: 1.5 }));
+            assert!(ring.points.contains(&Point { x: 4.5, y: 5.0 }));
+
+            // Lines cut off every corner of the image, each running across it
+            let layer = triangles.isoline(2.5);
+            assert_eq!(layer.paths.len(), 4, "{:?}", diagonal);
+            assert!(layer.paths.iter().all(|path| !path.circular));
+        }
+    }
+
+    #[test]
+    fn test_matches_squares_on_grid_edges() {
+        // Triangles cross the same grid edges as squares, only adding points on diagonals
+        let img = peak();
+        for threshold in [2.5, 4.5, 7.0] {
+            let squares = MarchingSquares::new(&img).isoline(threshold);
+            let triangles = MarchingTriangles::new(&img).isoline(threshold);
+            assert_eq!(squares.paths.len(), triangles.paths.len());
+
+            let on_grid = |point: &&Point<f32>| point.x.fract() == 0.0 || point.y.fract() == 0.0;
+            for (square, triangle) in squares.paths.iter().zip(&triangles.paths) {
+                assert_eq!(square.circular, triangle.circular);
+                let grid_points = triangle.points.iter().filter(on_grid).collect::<Vec<_>>();
+                assert_eq!(square.points.iter().collect::<Vec<_>>(), grid_points);
+            }
+        }
+    }
+
+    #[test]
+    fn test_saddle() {
+        // The diagonal decides which pair of corners is joined, there's no ambiguity left
+        let img = Image::new(vec![0.0, 4.0, 3.5, 0.9], 2, 2);
+        let cuts_top_left = |paths: &[Path]| {
+            paths.iter().any(|path| {
+                path.points.iter().any(|point| point.x == 0.0)
+                    && path.points.iter().any(|point| point.y == 0.0)
+            })
+        };
+
+        // Across the low corners the high ones are cut off
+        let fixed = MarchingTriangles::new(&img).isoline(1.0);
+        assert_eq!(fixed.paths.len(), 2);
+        assert!(!cuts_top_left(&fixed.paths));
+
+        // The high corners are closer in value, so they're joined and the low ones cut off
+        let data_driven = MarchingTriangles::new(&img)
+            .with_diagonal(Diagonal::DataDriven)
+            .isoline(1.0);
+        assert_eq!(data_driven.paths.len(), 2);
+        assert!(cuts_top_left(&data_driven.paths));
+    }
+
+    #[test]
+    fn test_missing_corner() {
+        let img = Image::new(vec![0.0, 2.0, 2.0, f32::NAN], 2, 2);
+
+        // The fixed diagonal touches the missing corner, leaving nothing to contour
+        let fixed = MarchingTriangles::new(&img).isoline(1.0);
+        assert!(fixed.paths.is_empty());
+
+        let data_driven = MarchingTriangles::new(&img)
+            .with_diagonal(Diagonal::DataDriven)
+            .isoline(1.0);
+        assert_eq!(
+            data_driven.paths,
+            vec![Path {
+                points: vec![Point { x: 0.0, y: 0.5 }, Point { x: 0.5, y: 0.0 }],
+                circular: false,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_isolines() {
+        let img = peak();
+        let triangles = MarchingTriangles::new(&img);
+        let layers = triangles.isolines(&[5.5, 2.5]);
+        assert_eq!(layers, vec![triangles.isoline(5.5), triangles.isoline(2.5)]);
+    }
+}
@@ -0,0 +1,202 @@
+use super::marching_squares::Path;
+use super::util::*;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Polygon {
+    pub exterior: Path,
+    pub holes: Vec<Path>,
+}
+
+/// Group closed paths into polygons with holes
+///
+/// Rings nested an even number of levels deep become exteriors and the rings directly
+/// inside them become their holes. Exteriors are wound counter-clockwise and holes
+/// clockwise, measured with the y axis pointing up as in RFC 7946.
+/// Open paths can't bound an area and are left out.
+pub fn assemble_polygons(paths: &[Path]) -> Vec<Polygon> {
+    let rings: Vec<&Path> = paths
+        .iter()
+        .filter(|path| path.circular && signed_area(path) != 0.0)
+        .collect();
+    let parents = containment_tree(&rings);
+
+    let mut known_depths: Vec<Option<usize>> = vec![None; rings.len()];
+    let depths: Vec<usize> = (0..rings.len())
+        .map(|i| ring_depth(i, &parents, &mut known_depths))
+        .collect();
+
+    let mut polygons = Vec::new();
+    let mut polygon_index: Vec<Option<usize>> = vec![None; rings.len()];
+    for (i, ring) in rings.iter().enumerate() {
+        if depths[i].is_multiple_of(2) {
+            polygon_index[i] = Some(polygons.len());
+            polygons.push(Polygon {
+                exterior: with_orientation(ring, true),
+                holes: Vec::new(),
+            });
+        }
+    }
+    for (i, ring) in rings.iter().enumerate() {
+        if let Some(polygon) = parents[i].and_then(|parent| polygon_index[parent]) {
+            if polygon_index[i].is_none() {
+                polygons[polygon].holes.push(with_orientation(ring, false));
+            }
+        }
+    }
+
+    polygons
+}
+
+/// For each ring, the index of the smallest ring that contains it
+fn containment_tree(rings: &[&Path]) -> Vec<Option<usize>> {
+    // Visit rings from largest to smallest so a ring's parent has always been placed already
+    let areas: Vec<f32> = rings.iter().map(|ring| signed_area(ring).abs()).collect();
+    let mut order: Vec<usize> = (0..rings.len()).collect();
+    order.sort_by(|a, b| areas[*b].total_cmp(&areas[*a]));
+
+    let mut parents: Vec<Option<usize>> = vec![None; rings.len()];
+    for (placed, ring) in order.iter().enumerate() {
+        let test_point = edge_midpoint(rings[*ring]);
+        parents[*ring] = order[..placed]
+            .iter()
+            .rev()
+            .find(|candidate| contains(rings[**candidate], &test_point))
+            .copied();
+    }
+
+    parents
+}
+
+fn ring_depth(ring: usize, parents: &[Option<usize>], depths: &mut [Option<usize>]) -> usize {
+    if let Some(depth) = depths[ring] {
+        return depth;
+    }
+
+    let depth = match parents[ring] {
+        Some(parent) => ring_depth(parent, parents, depths) + 1,
+        None => 0,
+    };
+    depths[ring] = Some(depth);
+    depth
+}
+
+/// Shoelace area of a closed path, positive when counter-clockwise with the y axis pointing up
+pub fn signed_area(path: &Path) -> f32 {
+    let points = &path.points;
+    (0..points.len())
+        .map(|i| {
+            let a = points[i];
+            let b = points[(i + 1) % points.len()];
+            a.x * b.y - b.x * a.y
+        })
+        .sum::<f32>()
+        / 2.0
+}
+
+/// Even-odd ray casting test of whether a point lies inside a closed path
+pub fn contains(path: &Path, point: &Point<f32>) -> bool {
+    let points = &path.points;
+    let mut inside = false;
+    for i in 0..points.len() {
+        let a = points[i];
+        let b = points[(i + 1) % points.len()];
+        if (a.y > point.y) != (b.y > point.y) {
+            let x_intersect = a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x);
+            if point.x < x_intersect {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+// Rings from the same isoline or isoband can touch at a vertex but never share an edge,
+// so the middle of an edge is a safe point for testing containment
+fn edge_midpoint(path: &Path) -> Point<f32> {
+    let a = path.points[0];
+    let b = path.points[1 % path.points.len()];
+    Point {
+        x: (a.x + b.x) / 2.0,
+        y: (a.y + b.y) / 2.0,
+    }
+}
+
+fn with_orientation(path: &Path, counter_clockwise: bool) -> Path {
+    let mut path = path.clone();
+    if (signed_area(&path) > 0.0) != counter_clockwise {
+        path.points.reverse();
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(min: f32, max: f32) -> Path {
+        Path {
+            points: vec![
+                Point { x: min, y: min },
+                Point { x: min, y: max },
+                Point { x: max, y: max },
+                Point { x: max, y: min },
+                Point { x: min, y: min },
+            ],
+            circular: true,
+        }
+    }
+
+    #[test]
+    fn test_signed_area() {
+        assert_eq!(signed_area(&square(0.0, 2.0)), -4.0);
+
+        let mut path = square(0.0, 2.0);
+        path.points.reverse();
+        assert_eq!(signed_area(&path), 4.0);
+    }
+
+    #[test]
+    fn test_contains() {
+        let path = square(0.0, 2.0);
+        assert!(contains(&path, &Point { x: 1.0, y: 1.0 }));
+        assert!(contains(&path, &Point { x: 0.5, y: 1.5 }));
+        assert!(!contains(&path, &Point { x: 3.0, y: 1.0 }));
+        assert!(!contains(&path, &Point { x: -1.0, y: 1.0 }));
+    }
+
+    #[test]
+    fn test_assemble_polygons() {
+        // An island inside a lake inside an island, next to a separate island
+        let paths = vec![
+            square(2.0, 3.0),
+            square(0.0, 5.0),
+            square(1.0, 4.0),
+            square(6.0, 7.0),
+            Path {
+                points: vec![Point { x: 0.0, y: 0.0 }, Point { x: 7.0, y: 7.0 }],
+                circular: false,
+            },
+        ];
+
+        let polygons = assemble_polygons(&paths);
+        assert_eq!(polygons.len(), 3);
+
+        let outer = polygons
+            .iter()
+            .find(|polygon| polygon.exterior.points.contains(&Point { x: 0.0, y: 0.0 }))
+            .unwrap();
+        assert_eq!(outer.holes.len(), 1);
+        assert!(outer.holes[0].points.contains(&Point { x: 1.0, y: 1.0 }));
+
+        let inner = polygons
+            .iter()
+            .find(|polygon| polygon.exterior.points.contains(&Point { x: 2.0, y: 2.0 }))
+            .unwrap();
+        assert!(inner.holes.is_empty());
+
+        for polygon in &polygons {
+            assert!(signed_area(&polygon.exterior) > 0.0);
+            assert!(polygon.holes.iter().all(|hole| signed_area(hole) < 0.0));
+        }
+    }
+}
@@ -0,0 +1,309 @@
+use super::util::*;
+
+#[cfg(not(target_arch = "wasm32"))]
+use rayon::prelude::*;
+
+/// Smallest and largest valid corner value under a cell or block of cells
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Bounds {
+    lower: f32,
+    upper: f32,
+}
+
+// A block without any valid corners never matches a query
+const EMPTY: Bounds = Bounds {
+    lower: f32::MAX,
+    upper: f32::MIN,
+};
+
+impl Bounds {
+    fn merge(self, other: Bounds) -> Bounds {
+        Bounds {
+            lower: self.lower.min(other.lower),
+            upper: self.upper.max(other.upper),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Level {
+    offset: usize,
+    width: u32,
+    height: u32,
+}
+
+/// Min/max pyramid over the cells of an image, answering the same queries as `TreeNode`
+///
+/// Level 0 holds the bounds of each cell, the square between four neighbouring samples,
+/// and every level above halves the resolution of the one below until a single block
+/// covers the whole image. All levels live in one flat array.
+pub struct MinMaxPyramid {
+    bounds: Vec<Bounds>,
+    levels: Vec<Level>,
+}
+
+impl MinMaxPyramid {
+    pub fn create(img: &Image<f32>) -> MinMaxPyramid {
+        let mut levels = vec![Level {
+            offset: 0,
+            width: img.width.saturating_sub(1).max(1),
+            height: img.height.saturating_sub(1).max(1),
+        }];
+        while levels
+            .last()
+            .is_some_and(|level| level.width > 1 || level.height > 1)
+        {
+            let below = levels[levels.len() - 1];
+            levels.push(Level {
+                offset: below.offset + below.width as usize * below.height as usize,
+                width: below.width.div_ceil(2),
+                height: below.height.div_ceil(2),
+            });
+        }
+
+        let top = levels[levels.len() - 1];
+        let mut bounds = vec![EMPTY; top.offset + 1];
+
+        let (cells, mut rest) =
+            bounds.split_at_mut(levels[0].width as usize * levels[0].height as usize);
+        fill_rows(cells, levels[0].width, |x, y| cell_bounds(img, x, y));
+
+        let mut below = &*cells;
+        for (index, level) in levels.iter().enumerate().skip(1) {
+            let (blocks, above) = rest.split_at_mut(level.width as usize * level.height as usize);
+            let below_level = levels[index - 1];
+            fill_rows(blocks, level.width, |x, y| {
+                block_bounds(below, below_level, x, y)
+            });
+            below = blocks;
+            rest = above;
+        }
+
+        MinMaxPyramid { bounds, levels }
+    }
+
+    /// Bytes held on the heap by the pyramid
+    pub fn heap_size(&self) -> usize {
+        self.bounds.capacity() * std::mem::size_of::<Bounds>()
+            + self.levels.capacity() * std::mem::size_of::<Level>()
+    }
+
+    pub fn under_threshold(&self, threshold: f32) -> Vec<Point<u32>> {
+        self.query(|bounds| bounds.lower <= threshold)
+    }
+
+    pub fn above_threshold(&self, threshold: f32) -> Vec<Point<u32>> {
+        self.query(|bounds| bounds.upper >= threshold)
+    }
+
+    /// Cells with corners on both sides of the threshold, `lower_bound < threshold <= upper_bound`
+    pub fn crossing(&self, threshold: f32) -> Vec<Point<u32>> {
+        self.query(|bounds| bounds.lower < threshold && threshold <= bounds.upper)
+    }
+
+    /// Cells whose values overlap the half-open band `[lower, upper)`
+    pub fn within_range(&self, lower: f32, upper: f32) -> Vec<Point<u32>> {
+        self.query(|bounds| bounds.upper >= lower && bounds.lower < upper)
+    }
+
+    // Every predicate holds for a block whenever it holds for any cell inside it,
+    // so blocks that fail can be skipped along with everything under them
+    fn query<F: Fn(&Bounds) -> bool>(&self, matches: F) -> Vec<Point<u32>> {
+        let mut cells = Vec::new();
+        let mut stack = vec![(self.levels.len() - 1, Point { x: 0, y: 0 })];
+
+        while let Some((index, block)) = stack.pop() {
+            let level = self.levels[index];
+            if !matches(&self.bounds[level.offset + (block.y * level.width + block.x) as usize]) {
+                continue;
+            }
+            if index == 0 {
+                cells.push(block);
+                continue;
+            }
+
+            // Pushed in reverse so the top left child is visited first
+            let below = self.levels[index - 1];
+            for (dx, dy) in &[(1, 1), (0, 1), (1, 0), (0, 0)] {
+                let child = Point {
+                    x: block.x * 2 + dx,
+                    y: block.y * 2 + dy,
+                };
+                if child.x < below.width && child.y < below.height {
+                    stack.push((index - 1, child));
+                }
+            }
+        }
+
+        cells
+    }
+}
+
+fn cell_bounds(img: &Image<f32>, x: u32, y: u32) -> Bounds {
+    [(0, 0), (1, 0), (0, 1), (1, 1)]
+        .iter()
+        .filter_map(|(dx, dy)| {
+            img.get_val(&Point {
+                x: x + dx,
+                y: y + dy,
+            })
+        })
+        .fold(EMPTY, |bounds, val| {
+            bounds.merge(Bounds {
+                lower: val,
+                upper: val,
+            })
+        })
+}
+
+fn block_bounds(below: &[Bounds], below_level: Level, x: u32, y: u32) -> Bounds {
+    [(0, 0), (1, 0), (0, 1), (1, 1)]
+        .iter()
+        .map(|(dx, dy)| (x * 2 + dx, y * 2 + dy))
+        .filter(|(x, y)| *x < below_level.width && *y < below_level.height)
+        .fold(EMPTY, |bounds, (x, y)| {
+            bounds.merge(below[(y * below_level.width + x) as usize])
+        })
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn fill_rows<F: Fn(u32, u32) -> Bounds + Sync>(level: &mut [Bounds], width: u32, bounds: F) {
+    level
+        .par_chunks_mut(width as usize)
+        .enumerate()
+        .for_each(|(y, row)| {
+            for (x, block) in row.iter_mut().enumerate() {
+                *block = bounds(x as u32, y as u32);
+            }
+        });
+}
+
+#[cfg(target_arch = "wasm32")]
+fn fill_rows<F: Fn(u32, u32) -> Bounds>(level: &mut [Bounds], width: u32, bounds: F) {
+    level
+        .chunks_mut(width as usize)
+        .enumerate()
+        .for_each(|(y, row)| {
+            for (x, block) in row.iter_mut().enumerate() {
+                *block = bounds(x as u32, y as u32);
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::quad_tree::TreeNode;
+    use super::*;
+
+    fn sorted(mut cells: Vec<Point<u32>>) -> Vec<Point<u32>> {
+        cells.sort_by_key(|cell| (cell.y, cell.x));
+        cells
+    }
+
+    #[test]
+    fn test_levels() {
+        let img = Image::new(vec![0.0; 7 * 4], 7, 4);
+        let pyramid = MinMaxPyramid::create(&img);
+
+        let dimensions: Vec<(u32, u32)> = pyramid
+            .levels
+            .iter()
+            .map(|level| (level.width, level.height))
+            .collect();
+        assert_eq!(dimensions, vec![(6, 3), (3, 2), (2, 1), (1, 1)]);
+        assert_eq!(pyramid.bounds.len(), 18 + 6 + 2 + 1);
+
+        let img = Image::new(vec![0.0], 1, 1);
+        let pyramid = MinMaxPyramid::create(&img);
+        assert_eq!(pyramid.levels.len(), 1);
+        assert_eq!(pyramid.bounds.len(), 1);
+    }
+
+    #[test]
+    fn test_bounds() {
+        #[rustfmt::skip]
+        let data = vec![
+            1.0, 2.0, 3.0,
+            4.0, f32::NAN, 6.0,
+            7.0, 8.0, 9.0,
+        ];
+        let pyramid = MinMaxPyramid::create(&Image::new(data, 3, 3));
+
+        assert_eq!(
+            pyramid.bounds[..4],
+            [
+                Bounds {
+                    lower: 1.0,
+                    upper: 4.0
+                },
+                Bounds {
+                    lower: 2.0,
+                    upper: 6.0
+                },
+                Bounds {
+                    lower: 4.0,
+                    upper: 8.0
+                },
+                Bounds {
+                    lower: 6.0,
+                    upper: 9.0
+                },
+            ]
+        );
+        assert_eq!(
+            pyramid.bounds[4],
+            Bounds {
+                lower: 1.0,
+                upper: 9.0
+            }
+        );
+    }
+
+    #[test]
+    fn test_matches_quad_tree() {
+        // Uneven dimensions with a bit of everything, including missing samples
+        let (width, height) = (13, 9);
+        let data: Vec<f32> = (0..width * height)
+            .map(|i| {
+                let (x, y) = ((i % width) as f32, (i / width) as f32);
+                if i % 17 == 5 {
+                    f32::NAN
+                } else {
+                    (x * 0.7).sin() * 5.0 + (y * 0.4).cos() * 3.0
+                }
+            })
+            .collect();
+        let img = Image::new(data, width, height);
+        let tree = TreeNode::create(&img);
+        let pyramid = MinMaxPyramid::create(&img);
+
+        for threshold in &[-7.0, -3.0, -0.5, 0.0, 1.5, 4.0, 8.5] {
+            assert_eq!(
+                sorted(pyramid.crossing(*threshold)),
+                sorted(tree.crossing(*threshold)),
+                "crossing {}",
+                threshold
+            );
+            assert_eq!(
+                sorted(pyramid.above_threshold(*threshold)),
+                sorted(tree.above_threshold(*threshold)),
+            );
+            assert_eq!(
+                sorted(pyramid.under_threshold(*threshold)),
+                sorted(tree.under_threshold(*threshold)),
+            );
+            assert_eq!(
+                sorted(pyramid.within_range(*threshold, threshold + 2.0)),
+                sorted(tree.within_range(*threshold, threshold + 2.0)),
+            );
+        }
+    }
+
+    #[test]
+    fn test_heap_size() {
+        let img = Image::new(vec![0.0; 100 * 100], 100, 100);
+        let pyramid = MinMaxPyramid::create(&img);
+        let tree = TreeNode::create(&img);
+        assert!(pyramid.heap_size() < tree.heap_size());
+    }
+}
@@ -18,6 +18,20 @@ impl TreeNode {
         create_node(img, Point { x: 0, y: 0 }, img.width, img.height)
     }
 
+    /// Bytes held on the heap by the nodes below this one
+    pub fn heap_size(&self) -> usize {
+        [
+            &self.top_left,
+            &self.top_right,
+            &self.bottom_left,
+            &self.bottom_right,
+        ]
+        .iter()
+        .filter_map(|child| child.as_deref())
+        .map(|child| std::mem::size_of::<TreeNode>() + child.heap_size())
+        .sum()
+    }
+
     pub fn under_threshold(&self, threshold: f32) -> Vec<Point<u32>> {
         if self.lower_bound > threshold {
             return Vec::new();
@@ -26,21 +40,25 @@ impl TreeNode {
         let mut cells: Vec<Point<u32>> = Vec::new();
 
         if self.width <= 2 && self.height <= 2 && self.lower_bound <= threshold {
-            cells.push(self.origin.clone());
+            cells.push(self.origin);
             return cells;
         }
 
-        let threshold_func = |node: &Box<TreeNode>| node.under_threshold(threshold);
-        cells.extend(self.top_left.as_ref().map_or(Vec::new(), &threshold_func));
-        cells.extend(self.top_right.as_ref().map_or(Vec::new(), &threshold_func));
+        let threshold_func = |node: &TreeNode| node.under_threshold(threshold);
+        cells.extend(self.top_left.as_deref().map_or(Vec::new(), &threshold_func));
+        cells.extend(
+            self.top_right
+                .as_deref()
+                .map_or(Vec::new(), &threshold_func),
+        );
         cells.extend(
             self.bottom_left
-                .as_ref()
+                .as_deref()
                 .map_or(Vec::new(), &threshold_func),
         );
         cells.extend(
             self.bottom_right
-                .as_ref()
+                .as_deref()
                 .map_or(Vec::new(), &threshold_func),
         );
 
@@ -55,26 +73,85 @@ impl TreeNode {
         let mut cells: Vec<Point<u32>> = Vec::new();
 
         if self.width <= 2 && self.height <= 2 && self.upper_bound >= threshold {
-            cells.push(self.origin.clone());
+            cells.push(self.origin);
             return cells;
         }
 
-        let threshold_func = |node: &Box<TreeNode>| node.above_threshold(threshold);
-        cells.extend(self.top_left.as_ref().map_or(Vec::new(), &threshold_func));
-        cells.extend(self.top_right.as_ref().map_or(Vec::new(), &threshold_func));
+        let threshold_func = |node: &TreeNode| node.above_threshold(threshold);
+        cells.extend(self.top_left.as_deref().map_or(Vec::new(), &threshold_func));
+        cells.extend(
+            self.top_right
+                .as_deref()
+                .map_or(Vec::new(), &threshold_func),
+        );
         cells.extend(
             self.bottom_left
-                .as_ref()
+                .as_deref()
                 .map_or(Vec::new(), &threshold_func),
         );
         cells.extend(
             self.bottom_right
-                .as_ref()
+                .as_deref()
                 .map_or(Vec::new(), &threshold_func),
         );
 
         cells
     }
+
+    /// Cells with corners on both sides of the threshold, `lower_bound < threshold <= upper_bound`
+    ///
+    /// Subtrees entirely above or entirely below the threshold can't hold a contour and are
+    /// skipped.
+    pub fn crossing(&self, threshold: f32) -> Vec<Point<u32>> {
+        if self.upper_bound < threshold || self.lower_bound >= threshold {
+            return Vec::new();
+        }
+
+        let mut cells: Vec<Point<u32>> = Vec::new();
+
+        if self.width <= 2 && self.height <= 2 {
+            cells.push(self.origin);
+            return cells;
+        }
+
+        let crossing_func = |node: &TreeNode| node.crossing(threshold);
+        cells.extend(self.top_left.as_deref().map_or(Vec::new(), &crossing_func));
+        cells.extend(self.top_right.as_deref().map_or(Vec::new(), &crossing_func));
+        cells.extend(
+            self.bottom_left
+                .as_deref()
+                .map_or(Vec::new(), &crossing_func),
+        );
+        cells.extend(
+            self.bottom_right
+                .as_deref()
+                .map_or(Vec::new(), &crossing_func),
+        );
+
+        cells
+    }
+
+    /// Cells whose values overlap the half-open band `[lower, upper)`
+    pub fn within_range(&self, lower: f32, upper: f32) -> Vec<Point<u32>> {
+        if self.upper_bound < lower || self.lower_bound >= upper {
+            return Vec::new();
+        }
+
+        let mut cells: Vec<Point<u32>> = Vec::new();
+
+        if self.width <= 2 && self.height <= 2 {
+            cells.push(self.origin);
+            return cells;
+        }
+
+        let range_func = |node: &TreeNode| node.within_range(lower, upper);
+        cells.extend(self.top_left.as_deref().map_or(Vec::new(), &range_func));
+        cells.extend(self.top_right.as_deref().map_or(Vec::new(), &range_func));
+        cells.extend(self.bottom_left.as_deref().map_or(Vec::new(), &range_func));
+        cells.extend(self.bottom_right.as_deref().map_or(Vec::new(), &range_func));
+
+        cells
+    }
 }
 
 fn create_node(img: &Image<f32>, origin: Point<u32>, width: u32, height: u32) -> TreeNode {
@@ -86,19 +163,20 @@ fn create_node(img: &Image<f32>, origin: Point<u32>, width: u32, height: u32) ->
         let offsets: [(u32, u32); 4] = [(0, 0), (0, 1), (1, 0), (1, 1)];
 
         for (x, y) in &offsets {
-            let point = &origin + Point { x: *x, y: *y };
+            let point = origin + Point { x: *x, y: *y };
 
-            img.get_val(&point).map(|val| values.push(val));
+            if let Some(val) = img.get_val(&point) {
+                values.push(val);
+            }
         }
 
-        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
-
+        // Missing samples are skipped, a cell without any valid corners never matches a query
         return TreeNode {
-            origin: origin,
-            lower_bound: *values.first().unwrap_or(&f32::MAX),
-            upper_bound: *values.last().unwrap_or(&f32::MIN),
-            width: width,
-            height: height,
+            origin,
+            lower_bound: values.iter().fold(f32::MAX, |min, val| min.min(*val)),
+            upper_bound: values.iter().fold(f32::MIN, |max, val| max.max(*val)),
+            width,
+            height,
             top_left: None,
             top_right: None,
             bottom_left: None,
@@ -109,17 +187,17 @@ fn create_node(img: &Image<f32>, origin: Point<u32>, width: u32, height: u32) ->
     let mut bottom_left: Option<Box<TreeNode>> = None;
     let mut bottom_right: Option<Box<TreeNode>> = None;
 
-    let next_width = if width <= 2 { width } else { (width + 1) / 2 };
+    let next_width = if width <= 2 { width } else { width.div_ceil(2) };
     let next_height = if height <= 2 {
         height
     } else {
-        (height + 1) / 2
+        height.div_ceil(2)
     };
 
     let mid_y = origin.y + next_height - 1;
     let bottom_height = height + 1 - next_height;
 
-    let top_left_tree = create_node(img, origin.clone(), next_width, next_height);
+    let top_left_tree = create_node(img, origin, next_width, next_height);
 
     min = if min < top_left_tree.lower_bound {
         min
@@ -205,11 +283,11 @@ fn create_node(img: &Image<f32>, origin: Point<u32>, width: u32, height: u32) ->
     }
 
     TreeNode {
-        origin: origin,
+        origin,
         lower_bound: min,
         upper_bound: max,
-        width: width,
-        height: height,
+        width,
+        height,
         top_left,
         top_right,
         bottom_left,
@@ -369,4 +447,60 @@ mod tests {
 
         println!("{:?}", cells);
     }
+
+    #[test]
+    fn test_crossing() {
+        #[rustfmt::skip]
+        let data = [
+            1, 2, 3, 4, 3, 2, 1,
+            2, 3, 4, 5, 4, 3, 2,
+            3, 4, 5, 6, 5, 4, 3,
+            4, 5, 6, 7, 6, 5, 4,
+            3, 4, 5, 6, 5, 4, 3,
+            2, 3, 4, 5, 4, 3, 2,
+            1, 2, 3, 4, 3, 2, 1,
+        ];
+        let img = Image::new(data.iter().map(|num| *num as f32).collect(), 7, 7);
+        let tree = TreeNode::create(&img);
+
+        for threshold in &[0.5, 1.0, 2.0, 3.5, 5.0, 7.0, 7.5] {
+            let mut cells = tree.crossing(*threshold);
+            cells.sort_by_key(|cell| (cell.y, cell.x));
+
+            let expected: Vec<Point<u32>> = (0..6)
+                .flat_map(|y| (0..6).map(move |x| Point { x, y }))
+                .filter(|cell| {
+                    let corners: Vec<f32> = [(0, 0), (1, 0), (0, 1), (1, 1)]
+                        .iter()
+                        .map(|(x, y)| img.get_val(&(cell + Point { x: *x, y: *y })).unwrap())
+                        .collect();
+                    corners.iter().any(|val| *val >= *threshold)
+                        && corners.iter().any(|val| *val < *threshold)
+                })
+                .collect();
+            assert_eq!(cells, expected, "threshold {}", threshold);
+        }
+
+        // Low thresholds no longer return every cell under the peak
+        assert_eq!(tree.above_threshold(2.0).len(), 36);
+        assert_eq!(tree.crossing(2.0).len(), 4);
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_missing_values() {
+        let nan = f32::NAN;
+        let data = [1.0, 2.0, nan, nan,
+                    3.0, 4.0, nan, -9.0,
+                    5.0, 6.0, 7.0, 8.0].to_vec();
+        let img = Image::new(data, 4, 3).with_nodata(-9.0);
+        let tree = create_node(&img, Point {x: 0, y: 0}, img.width, img.height);
+
+        assert_eq!(tree.lower_bound, 1.0);
+        assert_eq!(tree.upper_bound, 8.0);
+
+        let top_right = tree.top_right.unwrap();
+        assert_eq!(top_right.lower_bound, 2.0);
+        assert_eq!(top_right.upper_bound, 4.0);
+    }
 }
@@ -0,0 +1,332 @@
+use super::marching_squares::Path;
+use super::util::*;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+/// How isolines are thinned out to fewer points, with tolerances in sample space
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Simplification {
+    /// Drop points that are closer than `tolerance` to the simplified line
+    DouglasPeucker { tolerance: f32 },
+    /// Drop points that make a triangle smaller than `min_area` with their neighbours,
+    /// smallest first
+    VisvalingamWhyatt { min_area: f32 },
+}
+
+impl Simplification {
+    pub fn apply(&self, path: &Path) -> Path {
+        match self {
+            Simplification::DouglasPeucker { tolerance } => path.douglas_peucker(*tolerance),
+            Simplification::VisvalingamWhyatt { min_area } => path.visvalingam_whyatt(*min_area),
+        }
+    }
+
+    /// What it costs to replace `b` with the line from `a` to `c`, the triangle area for
+    /// Visvalingam-Whyatt. For Douglas-Peucker it's the distance from that line of the
+    /// farthest of `between`, every original point from `a` to `c` including any removed
+    /// already, so the tolerance holds for the whole path however many points go.
+    pub(crate) fn cost(
+        &self,
+        a: &Point<f32>,
+        b: &Point<f32>,
+        c: &Point<f32>,
+        between: &[Point<f32>],
+    ) -> f32 {
+        match self {
+            Simplification::DouglasPeucker { .. } => between
+                .iter()
+                .map(|point| distance_to_segment(point, a, c))
+                .fold(0.0, f32::max),
+            Simplification::VisvalingamWhyatt { .. } => triangle_area(a, b, c),
+        }
+    }
+
+    /// Whether a point with this cost is close enough to the line to be dropped
+    pub(crate) fn removes(&self, cost: f32) -> bool {
+        match self {
+            Simplification::DouglasPeucker { tolerance } => cost <= *tolerance,
+            Simplification::VisvalingamWhyatt { min_area } => cost < *min_area,
+        }
+    }
+}
+
+// Three distinct points and the repeat of the first
+pub(crate) const MIN_RING_POINTS: usize = 4;
+
+impl Path {
+    /// The path with every point removed that is within `tolerance` of the line
+    /// through the points that are kept
+    ///
+    /// Open paths keep their end points. Closed rings keep their first point and stay closed,
+    /// unless that would leave fewer than three corners, then they are left as they are.
+    pub fn douglas_peucker(&self, tolerance: f32) -> Path {
+        let points = &self.points;
+        if points.len() <= 2 {
+            return self.clone();
+        }
+
+        let mut keep = vec![false; points.len()];
+        keep[0] = true;
+        keep[points.len() - 1] = true;
+        let mut stack = vec![(0, points.len() - 1)];
+        while let Some((first, last)) = stack.pop() {
+            let farthest = (first + 1..last)
+                .map(|i| {
+                    (
+                        i,
+                        distance_to_segment(&points[i], &points[first], &points[last]),
+                    )
+                })
+                .max_by(|a, b| a.1.total_cmp(&b.1));
+            if let Some((i, distance)) = farthest {
+                if distance > tolerance {
+                    keep[i] = true;
+                    stack.push((first, i));
+                    stack.push((i, last));
+                }
+            }
+        }
+
+        self.keeping(&keep)
+    }
+
+    /// The path with points removed in order of the area of the triangle they make with their
+    /// neighbours, until every triangle left is at least `min_area`
+    ///
+    /// Open paths keep their end points. Closed rings keep their first point and at least
+    /// three corners, and stay closed.
+    pub fn visvalingam_whyatt(&self, min_area: f32) -> Path {
+        let points = &self.points;
+        let count = points.len();
+        if count <= 2 {
+            return self.clone();
+        }
+
+        let mut previous: Vec<usize> = (0..count).map(|i| i.saturating_sub(1)).collect();
+        let mut next: Vec<usize> = (0..count).map(|i| (i + 1).min(count - 1)).collect();
+        let area = |previous: usize, i: usize, next: usize| {
+            triangle_area(&points[previous], &points[i], &points[next])
+        };
+
+        // Areas go stale as neighbours are removed, so each entry records the neighbours it
+        // was worked out from and is skipped if they've changed
+        let mut heap: BinaryHeap<Corner> = (1..count - 1)
+            .map(|i| Corner {
+                cost: area(i - 1, i, i + 1),
+                path: 0,
+                index: i,
+                previous: i - 1,
+                next: i + 1,
+            })
+            .collect();
+
+        let mut keep = vec![true; count];
+        let mut remaining = count;
+        while let Some(corner) = heap.pop() {
+            let Corner { index: i, .. } = corner;
+            if !keep[i] || previous[i] != corner.previous || next[i] != corner.next {
+                continue;
+            }
+            if corner.cost >= min_area || (self.circular && remaining <= MIN_RING_POINTS) {
+                break;
+            }
+
+            keep[i] = false;
+            remaining -= 1;
+            let (before, after) = (previous[i], next[i]);
+            next[before] = after;
+            previous[after] = before;
+            for neighbour in [before, after] {
+                if neighbour != 0 && neighbour != count - 1 {
+                    let (previous, next) = (previous[neighbour], next[neighbour]);
+                    heap.push(Corner {
+                        cost: area(previous, neighbour, next),
+                        path: 0,
+                        index: neighbour,
+                        previous,
+                        next,
+                    });
+                }
+            }
+        }
+
+        self.keeping(&keep)
+    }
+
+    fn keeping(&self, keep: &[bool]) -> Path {
+        let points: Vec<Point<f32>> = self
+            .points
+            .iter()
+            .zip(keep)
+            .filter(|(_, keep)| **keep)
+            .map(|(point, _)| *point)
+            .collect();
+
+        if self.circular && points.len() < MIN_RING_POINTS {
+            return self.clone();
+        }
+        Path {
+            points,
+            circular: self.circular,
+        }
+    }
+}
+
+/// A point waiting to be removed, the cheapest first, with the neighbours its cost was worked
+/// out from so it can be skipped once they've changed
+pub(crate) struct Corner {
+    pub(crate) cost: f32,
+    // Which of several paths simplified together the point is on
+    pub(crate) path: usize,
+    pub(crate) index: usize,
+    pub(crate) previous: usize,
+    pub(crate) next: usize,
+}
+
+impl PartialEq for Corner {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Corner {}
+
+impl PartialOrd for Corner {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Corner {
+    // Reversed to make the max heap pop the cheapest point, earliest first on a tie
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .total_cmp(&self.cost)
+            .then(other.path.cmp(&self.path))
+            .then(other.index.cmp(&self.index))
+    }
+}
+
+fn distance_to_segment(point: &Point<f32>, start: &Point<f32>, end: &Point<f32>) -> f32 {
+    let (dx, dy) = (end.x - start.x, end.y - start.y);
+    let length_squared = dx * dx + dy * dy;
+    let t = if length_squared == 0.0 {
+        0.0
+    } else {
+        (((point.x - start.x) * dx + (point.y - start.y) * dy) / length_squared).clamp(0.0, 1.0)
+    };
+    (point.x - (start.x + t * dx)).hypot(point.y - (start.y + t * dy))
+}
+
+fn triangle_area(a: &Point<f32>, b: &Point<f32>, c: &Point<f32>) -> f32 {
+    ((b.x - a.x) * (c.y - a.y) - (c.x - a.x) * (b.y - a.y)).abs() / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::marching_squares::path;
+
+    #[test]
+    fn test_douglas_peucker() {
+        let wiggle = path(
+            &[
+                (0.0, 0.0),
+                (1.0, 0.1),
+                (2.0, -0.1),
+                (3.0, 5.0),
+                (4.0, 6.0),
+                (5.0, 7.0),
+            ],
+            false,
+        );
+        assert_eq!(
+            wiggle.douglas_peucker(0.5),
+            path(&[(0.0, 0.0), (2.0, -0.1), (3.0, 5.0), (5.0, 7.0)], false)
+        );
+        assert_eq!(
+            wiggle.douglas_peucker(10.0),
+            path(&[(0.0, 0.0), (5.0, 7.0)], false)
+        );
+        // Only points right on the line are dropped without any tolerance
+        assert_eq!(
+            wiggle.douglas_peucker(0.0),
+            path(
+                &[(0.0, 0.0), (1.0, 0.1), (2.0, -0.1), (3.0, 5.0), (5.0, 7.0)],
+                false
+            )
+        );
+
+        let octagon = path(
+            &[
+                (1.0, 0.0),
+                (2.0, 0.0),
+                (3.0, 1.0),
+                (3.0, 2.0),
+                (2.0, 3.0),
+                (1.0, 3.0),
+                (0.0, 2.0),
+                (0.0, 1.0),
+                (1.0, 0.0),
+            ],
+            true,
+        );
+        let simplified = octagon.douglas_peucker(1.0);
+        assert!(simplified.circular);
+        assert_eq!(simplified.points.first(), simplified.points.last());
+        assert!(simplified.points.len() >= MIN_RING_POINTS);
+        assert!(simplified.points.len() < octagon.points.len());
+
+        // Too small a ring to keep any shape is left alone
+        assert_eq!(octagon.douglas_peucker(10.0), octagon);
+    }
+
+    #[test]
+    fn test_visvalingam_whyatt() {
+        let wiggle = path(
+            &[
+                (0.0, 0.0),
+                (1.0, 0.1),
+                (2.0, -0.1),
+                (3.0, 5.0),
+                (4.0, 6.0),
+                (5.0, 7.0),
+            ],
+            false,
+        );
+        assert_eq!(
+            wiggle.visvalingam_whyatt(0.5),
+            path(&[(0.0, 0.0), (2.0, -0.1), (3.0, 5.0), (5.0, 7.0)], false)
+        );
+        assert_eq!(
+            wiggle.visvalingam_whyatt(100.0),
+            path(&[(0.0, 0.0), (5.0, 7.0)], false)
+        );
+        assert_eq!(wiggle.visvalingam_whyatt(0.0), wiggle);
+
+        // Rings are never cut down past a triangle
+        let square = path(
+            &[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0), (0.0, 0.0)],
+            true,
+        );
+        let triangle = square.visvalingam_whyatt(100.0);
+        assert!(triangle.circular);
+        assert_eq!(triangle.points.len(), MIN_RING_POINTS);
+        assert_eq!(triangle.points.first(), triangle.points.last());
+    }
+
+    #[test]
+    fn test_apply() {
+        let line = path(&[(0.0, 0.0), (1.0, 0.01), (2.0, 0.0)], false);
+        let straight = path(&[(0.0, 0.0), (2.0, 0.0)], false);
+        assert_eq!(
+            Simplification::DouglasPeucker { tolerance: 0.1 }.apply(&line),
+            straight
+        );
+        assert_eq!(
+            Simplification::VisvalingamWhyatt { min_area: 0.1 }.apply(&line),
+            straight
+        );
+    }
+}
@@ -0,0 +1,231 @@
+use super::error::{ContourError, ContourResult};
+use super::marching_squares::Path;
+use super::util::*;
+
+// Each pass doubles the points of every line, so this is already 256 times as many
+const MAX_CHAIKIN_ITERATIONS: u32 = 8;
+
+/// How the SVG writer rounds off the corners where isoline segments meet
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Smoothing {
+    /// Cut every corner this many times over, still drawn as straight lines
+    Chaikin { iterations: u32 },
+    /// Draw a cubic Bézier through each pair of points, following a cardinal spline that is
+    /// Catmull-Rom at a tension of 0 and straight lines at 1
+    CatmullRom { tension: f32 },
+}
+
+impl Smoothing {
+    /// Fails on more than 8 Chaikin iterations or a Catmull-Rom tension outside 0 to 1
+    pub fn check(&self) -> ContourResult<()> {
+        match self {
+            Smoothing::Chaikin { iterations } if *iterations > MAX_CHAIKIN_ITERATIONS => {
+                Err(ContourError::InvalidData(format!(
+                    "{} Chaikin iterations is more than the limit of {}",
+                    iterations, MAX_CHAIKIN_ITERATIONS
+                )))
+            }
+            Smoothing::CatmullRom { tension } if !(0.0..=1.0).contains(tension) => Err(
+                ContourError::InvalidData(format!("tension {} is not between 0 and 1", tension)),
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Path {
+    /// The path with each corner cut off a quarter of the way along the segments either side
+    ///
+    /// Open paths keep their end points, and closed rings are still closed.
+    pub fn chaikin(&self, iterations: u32) -> Path {
+        let mut path = Path {
+            points: self.distinct_points(),
+            circular: self.circular,
+        };
+        for _ in 0..iterations {
+            path = path.cut_corners();
+        }
+        path
+    }
+
+    // A threshold equal to a sample puts the same vertex on both edges meeting there
+    fn distinct_points(&self) -> Vec<Point<f32>> {
+        let mut points = self.points.clone();
+        points.dedup();
+        points
+    }
+
+    fn cut_corners(&self) -> Path {
+        let points = &self.points;
+        let last = points.len().saturating_sub(1);
+        let mut cut = Vec::with_capacity(points.len() * 2);
+        if !self.circular {
+            cut.extend(points.first());
+        }
+        for (i, pair) in points.windows(2).enumerate() {
+            if self.circular || i > 0 {
+                cut.push(lerp(&pair[0], &pair[1], 0.25));
+            }
+            if self.circular || i + 1 < last {
+                cut.push(lerp(&pair[0], &pair[1], 0.75));
+            }
+        }
+        if self.circular {
+            cut.extend(cut.first().copied());
+        } else {
+            cut.extend(points.last());
+        }
+
+        Path {
+            points: cut,
+            circular: self.circular,
+        }
+    }
+
+    /// Control points and end of the cubic Bézier from each point to the next
+    ///
+    /// The curve passes through every point of the path, closed rings run smoothly through
+    /// their first point and open paths start and end on theirs.
+    pub fn catmull_rom_beziers(&self, tension: f32) -> Vec<[Point<f32>; 3]> {
+        let points = self.distinct_points();
+        let count = points.len();
+        let scale = (1.0 - tension) / 6.0;
+
+        // Rings repeat their first point at the end, so wrap around before reaching it
+        let neighbour = |i: usize, offset: isize| -> &Point<f32> {
+            if self.circular && count > 1 {
+                let ring = count as isize - 1;
+                &points[(i as isize + offset).rem_euclid(ring) as usize]
+            } else {
+                &points[(i as isize + offset).clamp(0, count as isize - 1) as usize]
+            }
+        };
+
+        (0..count.saturating_sub(1))
+            .map(|i| {
+                let (before, start) = (neighbour(i, -1), neighbour(i, 0));
+                let (end, after) = (neighbour(i, 1), neighbour(i, 2));
+                [
+                    lerp(start, &offset(start, end, before), scale),
+                    lerp(end, &offset(end, start, after), scale),
+                    *end,
+                ]
+            })
+            .collect()
+    }
+}
+
+fn lerp(a: &Point<f32>, b: &Point<f32>, t: f32) -> Point<f32> {
+    Point {
+        x: a.x + (b.x - a.x) * t,
+        y: a.y + (b.y - a.y) * t,
+    }
+}
+
+/// `point` moved by the vector from `from` to `to`
+fn offset(point: &Point<f32>, to: &Point<f32>, from: &Point<f32>) -> Point<f32> {
+    Point {
+        x: point.x + to.x - from.x,
+        y: point.y + to.y - from.y,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::marching_squares::path;
+
+    #[test]
+    fn test_chaikin() {
+        let open = path(&[(0.0, 0.0), (4.0, 0.0), (4.0, 4.0)], false);
+        assert_eq!(
+            open.chaikin(1),
+            path(&[(0.0, 0.0), (3.0, 0.0), (4.0, 1.0), (4.0, 4.0)], false)
+        );
+        let smoothed = open.chaikin(3);
+        assert_eq!(smoothed.points.first(), open.points.first());
+        assert_eq!(smoothed.points.last(), open.points.last());
+
+        let square = path(
+            &[(0.0, 0.0), (4.0, 0.0), (4.0, 4.0), (0.0, 4.0), (0.0, 0.0)],
+            true,
+        );
+        let octagon = square.chaikin(1);
+        assert!(octagon.circular);
+        assert_eq!(octagon.points.len(), 9);
+        assert_eq!(octagon.points.first(), octagon.points.last());
+        assert_eq!(octagon.points[0], Point { x: 1.0, y: 0.0 });
+
+        // A single segment has no corners to cut
+        let line = path(&[(0.0, 0.0), (1.0, 1.0)], false);
+        assert_eq!(line.chaikin(2), line);
+        assert_eq!(open.chaikin(0), open);
+    }
+
+    #[test]
+    fn test_check() {
+        assert!(Smoothing::Chaikin { iterations: 8 }.check().is_ok());
+        assert!(Smoothing::CatmullRom { tension: 0.0 }.check().is_ok());
+        assert!(Smoothing::CatmullRom { tension: 1.0 }.check().is_ok());
+        for smoothing in [
+            Smoothing::Chaikin { iterations: 9 },
+            Smoothing::CatmullRom { tension: -0.1 },
+            Smoothing::CatmullRom { tension: 1.5 },
+            Smoothing::CatmullRom { tension: f32::NAN },
+        ] {
+            assert!(
+                matches!(smoothing.check(), Err(ContourError::InvalidData(_))),
+                "{:?}",
+                smoothing
+            );
+        }
+    }
+
+    #[test]
+    fn test_catmull_rom_beziers() {
+        let open = path(&[(0.0, 0.0), (3.0, 0.0), (3.0, 3.0)], false);
+        let curves = open.catmull_rom_beziers(0.0);
+        assert_eq!(curves.len(), 2);
+        assert_eq!(
+            curves[0],
+            [
+                Point { x: 0.5, y: 0.0 },
+                Point { x: 2.5, y: -0.5 },
+                Point { x: 3.0, y: 0.0 }
+            ]
+        );
+        assert_eq!(curves[1][2], Point { x: 3.0, y: 3.0 });
+
+        // Full tension leaves the control points on the ends, drawing straight lines
+        for (curve, pair) in open
+            .catmull_rom_beziers(1.0)
+            .iter()
+            .zip(open.points.windows(2))
+        {
+            assert_eq!(curve, &[pair[0], pair[1], pair[1]]);
+        }
+
+        // A ring goes all the way round, with the same tangent leaving and entering its start
+        let square = path(
+            &[(0.0, 0.0), (2.0, 0.0), (2.0, 2.0), (0.0, 2.0), (0.0, 0.0)],
+            true,
+        );
+        let curves = square.catmull_rom_beziers(0.0);
+        assert_eq!(curves.len(), 4);
+        assert_eq!(curves[3][2], square.points[0]);
+        assert_eq!(
+            curves[0][0],
+            Point {
+                x: 1.0 / 3.0,
+                y: -1.0 / 3.0
+            }
+        );
+        assert_eq!(
+            curves[3][1],
+            Point {
+                x: -1.0 / 3.0,
+                y: 1.0 / 3.0
+            }
+        );
+    }
+}
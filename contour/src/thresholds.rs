@@ -0,0 +1,462 @@
+use super::error::{ContourError, ContourResult};
+use super::util::*;
+
+// Guards against so many levels, asked for directly or by an interval too small for the data
+// range, that contouring would never finish
+const MAX_LEVELS: usize = 10_000;
+
+/// How to pick the levels to contour an image at
+#[derive(Clone, Debug, PartialEq)]
+pub enum Thresholds {
+    /// `count` evenly spaced levels strictly between the smallest and largest value
+    Count(usize),
+    /// Roughly `count` levels on round numbers, such as every 20 or every 0.5
+    NiceCount(usize),
+    /// `count` levels splitting the samples into groups of equal size, so each band covers
+    /// the same area
+    Quantile(usize),
+    /// `count` levels evenly spaced on a log scale between the smallest positive value and
+    /// the largest value
+    Logarithmic(usize),
+    /// `count` levels at the Jenks natural breaks, which keep values within each band close
+    /// together
+    NaturalBreaks(usize),
+    /// Every `base + k * interval` that lies between the smallest and largest value
+    Interval { interval: f64, base: f64 },
+    /// Exactly these levels
+    Explicit(Vec<f32>),
+}
+
+impl Thresholds {
+    /// Pick `count` levels by scheme name, one of "equal", "nice", "quantile", "log" or "jenks"
+    pub fn from_scheme(scheme: &str, count: usize) -> ContourResult<Thresholds> {
+        match scheme {
+            "equal" => Ok(Thresholds::Count(count)),
+            "nice" => Ok(Thresholds::NiceCount(count)),
+            "quantile" => Ok(Thresholds::Quantile(count)),
+            "log" => Ok(Thresholds::Logarithmic(count)),
+            "jenks" => Ok(Thresholds::NaturalBreaks(count)),
+            _ => Err(ContourError::InvalidData(format!(
+                "unknown threshold scheme {}",
+                scheme
+            ))),
+        }
+    }
+
+    pub fn levels(&self, img: &Image<f32>) -> ContourResult<Vec<f32>> {
+        let requested = match self {
+            Thresholds::Count(count)
+            | Thresholds::NiceCount(count)
+            | Thresholds::Quantile(count)
+            | Thresholds::Logarithmic(count)
+            | Thresholds::NaturalBreaks(count) => *count,
+            Thresholds::Explicit(levels) => levels.len(),
+            // Checked once the data range gives the number of levels
+            Thresholds::Interval { .. } => 0,
+        };
+        if requested > MAX_LEVELS {
+            return Err(ContourError::InvalidData(format!(
+                "{} levels is more than the limit of {}",
+                requested, MAX_LEVELS
+            )));
+        }
+
+        match (self, value_range(img)) {
+            (Thresholds::Explicit(levels), _) => Ok(levels.clone()),
+            (_, None) => Ok(Vec::new()),
+            (Thresholds::Count(count), Some((min, max))) => Ok(equal_count(min, max, *count)),
+            (Thresholds::NiceCount(count), Some((min, max))) => Ok(nice_count(min, max, *count)),
+            (Thresholds::Quantile(count), Some(range)) => {
+                Ok(Histogram::new(img, range).quantile_breaks(*count))
+            }
+            (Thresholds::Logarithmic(count), Some((_, max))) => logarithmic(img, max, *count),
+            (Thresholds::NaturalBreaks(count), Some(range)) => {
+                Ok(Histogram::new(img, range).natural_breaks(*count))
+            }
+            (Thresholds::Interval { interval, base }, Some((min, max))) => {
+                fixed_interval(min, max, *interval, *base)
+            }
+        }
+    }
+}
+
+/// Smallest and largest value in the image, ignoring missing samples
+pub fn value_range(img: &Image<f32>) -> Option<(f32, f32)> {
+    img.values().fold(None, |range, val| match range {
+        Some((min, max)) => Some((f32::min(min, val), f32::max(max, val))),
+        None => Some((val, val)),
+    })
+}
+
+pub fn equal_count(min: f32, max: f32, count: usize) -> Vec<f32> {
+    let step = (max as f64 - min as f64) / (count + 1) as f64;
+    (1..=count)
+        .map(|i| (min as f64 + step * i as f64) as f32)
+        .collect()
+}
+
+pub fn fixed_interval(min: f32, max: f32, interval: f64, base: f64) -> ContourResult<Vec<f32>> {
+    if !(interval.is_finite() && interval > 0.0 && base.is_finite()) {
+        return Err(ContourError::InvalidData(format!(
+            "interval {} from base {} does not give any levels",
+            interval, base
+        )));
+    }
+
+    // Work out each level from the base rather than stepping, so rounding errors don't build up
+    let first = ((min as f64 - base) / interval).floor() as i64 + 1;
+    let last = ((max as f64 - base) / interval).ceil() as i64 - 1;
+    let count = (last - first + 1).max(0) as usize;
+    if count > MAX_LEVELS {
+        return Err(ContourError::InvalidData(format!(
+            "interval {} gives {} levels, more than the limit of {}",
+            interval, count, MAX_LEVELS
+        )));
+    }
+
+    Ok((first..=last)
+        .map(|k| (base + k as f64 * interval) as f32)
+        .filter(|level| *level > min && *level < max)
+        .collect())
+}
+
+/// Levels on multiples of a round interval, close to `count` of them
+pub fn nice_count(min: f32, max: f32, count: usize) -> Vec<f32> {
+    let range = max as f64 - min as f64;
+    if count == 0 || range <= 0.0 {
+        return Vec::new();
+    }
+
+    let interval = nice_number(range / (count + 1) as f64);
+    fixed_interval(min, max, interval, 0.0).unwrap_or_default()
+}
+
+pub fn logarithmic(img: &Image<f32>, max: f32, count: usize) -> ContourResult<Vec<f32>> {
+    let min = img
+        .values()
+        .filter(|val| *val > 0.0)
+        .fold(f32::INFINITY, f32::min);
+    if !min.is_finite() {
+        return Err(ContourError::InvalidData(
+            "logarithmic levels need some values above zero".to_string(),
+        ));
+    }
+
+    let (log_min, log_max) = ((min as f64).ln(), (max as f64).ln());
+    let step = (log_max - log_min) / (count + 1) as f64;
+    Ok((1..=count)
+        .map(|i| (log_min + step * i as f64).exp() as f32)
+        .collect())
+}
+
+// Enough bins to place breaks well within the data range while keeping Jenks quick
+const HISTOGRAM_BINS: usize = 1024;
+
+/// Sample counts in equal width bins between the smallest and largest value
+struct Histogram {
+    min: f64,
+    bin_width: f64,
+    counts: Vec<u64>,
+}
+
+impl Histogram {
+    fn new(img: &Image<f32>, (min, max): (f32, f32)) -> Histogram {
+        let (min, max) = (min as f64, max as f64);
+        let bin_width = (max - min) / HISTOGRAM_BINS as f64;
+
+        let mut counts = vec![0; HISTOGRAM_BINS];
+        for val in img.values() {
+            let bin = if bin_width > 0.0 {
+                ((val as f64 - min) / bin_width) as usize
+            } else {
+                0
+            };
+            counts[bin.min(HISTOGRAM_BINS - 1)] += 1;
+        }
+
+        Histogram {
+            min,
+            bin_width,
+            counts,
+        }
+    }
+
+    fn bin_centre(&self, bin: usize) -> f64 {
+        self.min + (bin as f64 + 0.5) * self.bin_width
+    }
+
+    /// The value below which a `fraction` of the samples fall, spreading each bin's samples
+    /// evenly across it
+    fn quantile(&self, fraction: f64) -> f64 {
+        let total: u64 = self.counts.iter().sum();
+        let target = fraction * total as f64;
+
+        let mut below = 0.0;
+        for (bin, count) in self.counts.iter().enumerate() {
+            let count = *count as f64;
+            if count > 0.0 && below + count >= target {
+                let within = (target - below) / count;
+                return self.min + (bin as f64 + within) * self.bin_width;
+            }
+            below += count;
+        }
+        self.min + HISTOGRAM_BINS as f64 * self.bin_width
+    }
+
+    fn quantile_breaks(&self, count: usize) -> Vec<f32> {
+        let mut levels: Vec<f32> = (1..=count)
+            .map(|i| self.quantile(i as f64 / (count + 1) as f64) as f32)
+            .collect();
+        // Heavily repeated values can put several quantiles in the same place
+        levels.dedup();
+        levels
+    }
+
+    /// Jenks natural breaks, minimising the spread of values within each of `count + 1` classes
+    ///
+    /// Runs the usual dynamic program over the occupied bins, each weighted by its count,
+    /// and places each break halfway between the last bin of a class and the first of the next.
+    fn natural_breaks(&self, count: usize) -> Vec<f32> {
+        let bins: Vec<(f64, f64)> = self
+            .counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(bin, count)| (self.bin_centre(bin), *count as f64))
+            .collect();
+        let classes = count + 1;
+        let between = |bin: usize| ((bins[bin - 1].0 + bins[bin].0) / 2.0) as f32;
+        if bins.len() <= classes {
+            return (1..bins.len()).map(between).collect();
+        }
+
+        // Prefix sums of weight, weight * value and weight * value^2 give each class's squared
+        // deviation in constant time
+        let mut sums = vec![(0.0, 0.0, 0.0); bins.len() + 1];
+        for (i, (value, weight)) in bins.iter().enumerate() {
+            let (w, wx, wxx) = sums[i];
+            sums[i + 1] = (
+                w + weight,
+                wx + weight * value,
+                wxx + weight * value * value,
+            );
+        }
+        let deviation = |start: usize, end: usize| {
+            let w = sums[end].0 - sums[start].0;
+            let wx = sums[end].1 - sums[start].1;
+            let wxx = sums[end].2 - sums[start].2;
+            wxx - wx * wx / w
+        };
+
+        // cost[c][end] is the least total deviation of the first `end` bins split into c + 1
+        // classes, and first[c][end] the bin that starts the last of those classes
+        let n = bins.len();
+        let mut cost = vec![vec![f64::INFINITY; n + 1]; classes];
+        let mut first = vec![vec![0; n + 1]; classes];
+        for (end, cost) in cost[0].iter_mut().enumerate().skip(1) {
+            *cost = deviation(0, end);
+        }
+        for c in 1..classes {
+            for end in (c + 1)..=n {
+                for start in c..end {
+                    let total = cost[c - 1][start] + deviation(start, end);
+                    if total < cost[c][end] {
+                        cost[c][end] = total;
+                        first[c][end] = start;
+                    }
+                }
+            }
+        }
+
+        let mut starts = Vec::with_capacity(count);
+        let mut end = n;
+        for c in (1..classes).rev() {
+            let start = first[c][end];
+            starts.push(start);
+            end = start;
+        }
+        starts.reverse();
+        starts.into_iter().map(between).collect()
+    }
+}
+
+// From Heckbert's "Nice Numbers for Graph Labels", a 1, 2 or 5 times a power of ten near `value`
+fn nice_number(value: f64) -> f64 {
+    let exponent = value.log10().floor();
+    let fraction = value / 10f64.powf(exponent);
+    let nice_fraction = match fraction {
+        f if f < 1.5 => 1.0,
+        f if f < 3.0 => 2.0,
+        f if f < 7.0 => 5.0,
+        _ => 10.0,
+    };
+    nice_fraction * 10f64.powf(exponent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_range() {
+        let img = Image::new(vec![3.0, f32::NAN, -2.0, 8.0], 2, 2);
+        assert_eq!(value_range(&img), Some((-2.0, 8.0)));
+
+        let img = Image::new(vec![f32::NAN; 4], 2, 2);
+        assert_eq!(value_range(&img), None);
+    }
+
+    #[test]
+    fn test_equal_count() {
+        assert_eq!(equal_count(0.0, 100.0, 4), vec![20.0, 40.0, 60.0, 80.0]);
+        assert_eq!(equal_count(0.0, 100.0, 0), Vec::<f32>::new());
+    }
+
+    #[test]
+    fn test_fixed_interval() {
+        assert_eq!(
+            fixed_interval(0.0, 100.0, 25.0, 0.0).unwrap(),
+            vec![25.0, 50.0, 75.0]
+        );
+        assert_eq!(
+            fixed_interval(-12.0, 31.0, 10.0, 5.0).unwrap(),
+            vec![-5.0, 5.0, 15.0, 25.0]
+        );
+        assert_eq!(
+            fixed_interval(0.0, 1.0, 0.1, 0.0).unwrap(),
+            vec![0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8, 0.9]
+        );
+        assert!(fixed_interval(0.0, 1.0, 0.0, 0.0).is_err());
+        assert!(fixed_interval(0.0, 1.0, -1.0, 0.0).is_err());
+        assert!(fixed_interval(0.0, 1e9, 1.0, 0.0).is_err());
+    }
+
+    #[test]
+    fn test_nice_count() {
+        assert_eq!(nice_number(0.23), 0.2);
+        assert_eq!(nice_number(340.0), 500.0);
+        assert_eq!(nice_number(120.0), 100.0);
+        assert_eq!(nice_number(8.0), 10.0);
+
+        assert_eq!(nice_count(3.7, 97.2, 4), vec![20.0, 40.0, 60.0, 80.0]);
+        assert_eq!(
+            nice_count(0.13, 0.91, 8),
+            vec![0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8, 0.9]
+        );
+        assert_eq!(nice_count(5.0, 5.0, 4), Vec::<f32>::new());
+    }
+
+    #[test]
+    fn test_logarithmic() {
+        let img = Image::new(vec![-5.0, 0.0, 1.0, 10000.0], 2, 2);
+        let levels = logarithmic(&img, 10000.0, 3).unwrap();
+        let expected = [10.0, 100.0, 1000.0];
+        for (level, expected) in levels.iter().zip(&expected) {
+            assert!((level - expected).abs() < expected * 1e-5);
+        }
+
+        let img = Image::new(vec![-5.0, 0.0, -1.0, -2.0], 2, 2);
+        assert!(logarithmic(&img, 0.0, 3).is_err());
+    }
+
+    #[test]
+    fn test_quantile_breaks() {
+        // Most of the samples are small, with a long tail
+        let data: Vec<f32> = (0..100)
+            .map(|i| {
+                if i < 75 {
+                    i as f32 / 75.0
+                } else {
+                    (i - 74) as f32 * 40.0
+                }
+            })
+            .collect();
+        let img = Image::new(data, 10, 10);
+        let histogram = Histogram::new(&img, value_range(&img).unwrap());
+
+        let levels = histogram.quantile_breaks(3);
+        assert_eq!(levels.len(), 3);
+        assert!(levels[0] > 0.2 && levels[0] < 0.4);
+        assert!(levels[1] > 0.6 && levels[1] < 0.75);
+        assert!(levels[2] < 10.0);
+
+        // Equal spacing would have put every level in the tail
+        assert!(equal_count(0.0, 1040.0, 3).iter().all(|level| *level > 1.0));
+
+        let img = Image::new(vec![2.0; 4], 2, 2);
+        let histogram = Histogram::new(&img, (2.0, 2.0));
+        assert_eq!(histogram.quantile_breaks(3), vec![2.0]);
+    }
+
+    #[test]
+    fn test_natural_breaks() {
+        #[rustfmt::skip]
+        let data = vec![
+            1.0, 1.2, 0.8, 1.1,
+            5.0, 5.3, 4.9, 5.1,
+            20.0, 21.0, 19.5, 20.2,
+        ];
+        let img = Image::new(data, 4, 3);
+        let histogram = Histogram::new(&img, value_range(&img).unwrap());
+
+        let levels = histogram.natural_breaks(2);
+        assert_eq!(levels.len(), 2);
+        assert!(levels[0] > 1.2 && levels[0] < 4.9);
+        assert!(levels[1] > 5.3 && levels[1] < 19.5);
+
+        // More classes than distinct values puts a break between each of them
+        let img = Image::new(vec![1.0, 3.0, 1.0, 3.0], 2, 2);
+        let histogram = Histogram::new(&img, (1.0, 3.0));
+        let levels = histogram.natural_breaks(4);
+        assert_eq!(levels.len(), 1);
+        assert!((levels[0] - 2.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_from_scheme() {
+        assert_eq!(
+            Thresholds::from_scheme("jenks", 5).unwrap(),
+            Thresholds::NaturalBreaks(5)
+        );
+        assert!(Thresholds::from_scheme("fibonacci", 5).is_err());
+    }
+
+    #[test]
+    fn test_levels() {
+        #[rustfmt::skip]
+        let img = Image::new(vec![
+            0.0, 10.0,
+            30.0, 40.0,
+        ], 2, 2);
+
+        assert_eq!(
+            Thresholds::Count(3).levels(&img).unwrap(),
+            vec![10.0, 20.0, 30.0]
+        );
+        assert_eq!(
+            Thresholds::Interval {
+                interval: 15.0,
+                base: 5.0
+            }
+            .levels(&img)
+            .unwrap(),
+            vec![5.0, 20.0, 35.0]
+        );
+        assert_eq!(
+            Thresholds::Explicit(vec![7.0, 1.0]).levels(&img).unwrap(),
+            vec![7.0, 1.0]
+        );
+        assert_eq!(
+            Thresholds::NiceCount(3).levels(&img).unwrap(),
+            vec![10.0, 20.0, 30.0]
+        );
+
+        // Every way of asking for levels has the same limit
+        assert!(Thresholds::Count(MAX_LEVELS).levels(&img).is_ok());
+        assert!(Thresholds::Count(MAX_LEVELS + 1).levels(&img).is_err());
+        assert!(Thresholds::Quantile(MAX_LEVELS + 1).levels(&img).is_err());
+        assert!(Thresholds::Explicit(vec![1.0; MAX_LEVELS + 1])
+            .levels(&img)
+            .is_err());
+    }
+}
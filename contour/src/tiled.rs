@@ -0,0 +1,359 @@
+use super::error::{ContourError, ContourResult};
+use super::geojson::{isoline_feature, FeatureCollection};
+use super::geotiff::{self, GeoTransform};
+use super::marching_squares::{stitch_fragments, Fragment, IsolineLayer, MarchingSquares};
+use super::util::*;
+use super::{check_dimensions, check_thresholds, decoded_to_f32, prepare_samples};
+use std::io::{Read, Seek};
+use tiff::decoder::{ChunkType, Decoder};
+
+/// Isolines of a TIFF that was read and contoured a tile at a time
+#[derive(Clone, Debug, PartialEq)]
+pub struct TiledIsolines {
+    pub width: u32,
+    pub height: u32,
+    pub geotransform: Option<GeoTransform>,
+    pub layers: Vec<IsolineLayer>,
+}
+
+impl TiledIsolines {
+    pub fn to_geojson(&self) -> FeatureCollection {
+        FeatureCollection::new(
+            self.layers
+                .iter()
+                .map(|layer| isoline_feature(layer, self.geotransform.as_ref()))
+                .collect(),
+        )
+    }
+}
+
+/// Contour a TIFF too large to decode in one go, holding about `max_tile_bytes` of samples
+///
+/// Strips, or rows of TIFF tiles, are decoded one at a time into tiles of whole rows, each
+/// tile sharing its first row with the last row of the one above so no cell is missed. Paths
+/// cut where one tile meets the next are joined back up into continuous lines.
+///
+/// The budget is approximate. It covers the samples of the tile being contoured, not the
+/// lines found so far or the working memory of contouring a tile, and a single strip or row
+/// of TIFF tiles larger than the budget is still decoded whole, as the TIFF decoder can't
+/// read part of one.
+pub fn tiled_isolines_from_tiff<R: Read + Seek>(
+    reader: R,
+    thresholds: &[f32],
+    max_tile_bytes: usize,
+) -> ContourResult<TiledIsolines> {
+    check_thresholds(thresholds)?;
+    let mut decoder = Decoder::new(reader)?;
+
+    let nodata = geotiff::read_nodata(&mut decoder)?;
+    let scale_offset = geotiff::read_scale_offset(&mut decoder)?;
+    let geotransform = geotiff::read_geotransform(&mut decoder)?;
+    let (width, height) = decoder.dimensions()?;
+    check_dimensions(width, height, width as usize * height as usize)?;
+
+    let row_bytes = width as usize * std::mem::size_of::<f32>();
+    let tile_rows = (max_tile_bytes / row_bytes).min(height as usize) as u32;
+    if tile_rows < 2.min(height) {
+        return Err(ContourError::InvalidData(format!(
+            "a tile budget of {} bytes can't hold two rows of {} samples",
+            max_tile_bytes, width
+        )));
+    }
+
+    let mut tiler = Tiler::new(thresholds, width, tile_rows);
+    match decoder.get_chunk_type() {
+        ChunkType::Strip => {
+            for strip in 0..decoder.strip_count()? {
+                let samples = decoded_to_f32(decoder.read_chunk(strip)?);
+                let (_, strip_rows) = decoder.chunk_data_dimensions(strip);
+                // Pixels with several samples decode to a multiple of the expected length
+                check_dimensions(width, strip_rows, samples.len())?;
+                tiler.push(&prepare_samples(samples, nodata, &scale_offset));
+            }
+        }
+        ChunkType::Tile => {
+            let (tile_width, _) = decoder.chunk_dimensions();
+            let tiles_across = width.div_ceil(tile_width);
+            for tile_row in 0..decoder.tile_count()? / tiles_across {
+                let band = read_tile_row(&mut decoder, tile_row, tiles_across, width)?;
+                tiler.push(&prepare_samples(band, nodata, &scale_offset));
+            }
+        }
+    }
+
+    Ok(TiledIsolines {
+        width,
+        height,
+        geotransform,
+        layers: tiler.finish(),
+    })
+}
+
+/// The rows of the image covered by one row of TIFF tiles, with the tiles side by side
+fn read_tile_row<R: Read + Seek>(
+    decoder: &mut Decoder<R>,
+    tile_row: u32,
+    tiles_across: u32,
+    width: u32,
+) -> ContourResult<Vec<f32>> {
+    let (tile_width, _) = decoder.chunk_dimensions();
+    let mut band = Vec::new();
+    for column in 0..tiles_across {
+        let index = tile_row * tiles_across + column;
+        let samples = decoded_to_f32(decoder.read_chunk(index)?);
+        // Tiles at the right and bottom edges are cropped to the image
+        let (columns, rows) = decoder.chunk_data_dimensions(index);
+        check_dimensions(columns, rows, samples.len())?;
+        if band.is_empty() {
+            band = vec![0.0; width as usize * rows as usize];
+        }
+        let left = (column * tile_width) as usize;
+        for (row, samples) in samples.chunks(columns as usize).enumerate() {
+            let start = row * width as usize + left;
+            band[start..start + samples.len()].copy_from_slice(samples);
+        }
+    }
+    Ok(band)
+}
+
+struct Tiler<'a> {
+    thresholds: &'a [f32],
+    width: u32,
+    tile_rows: u32,
+    // Samples of the tile being filled, starting at row `top` of the image
+    tile: Vec<f32>,
+    top: u32,
+    fragments: Vec<Vec<Fragment>>,
+}
+
+impl Tiler<'_> {
+    fn new(thresholds: &[f32], width: u32, tile_rows: u32) -> Tiler<'_> {
+        Tiler {
+            thresholds,
+            width,
+            tile_rows,
+            tile: Vec::with_capacity(width as usize * tile_rows as usize),
+            top: 0,
+            fragments: vec![Vec::new(); thresholds.len()],
+        }
+    }
+
+    fn push(&mut self, mut samples: &[f32]) {
+        let capacity = self.width as usize * self.tile_rows as usize;
+        while !samples.is_empty() {
+            let (head, rest) = samples.split_at((capacity - self.tile.len()).min(samples.len()));
+            self.tile.extend_from_slice(head);
+            samples = rest;
+            if self.tile.len() == capacity {
+                self.contour_tile();
+            }
+        }
+    }
+
+    fn contour_tile(&mut self) {
+        let rows = (self.tile.len() / self.width as usize) as u32;
+        let img = Image::new(std::mem::take(&mut self.tile), self.width, rows);
+        let levels = MarchingSquares::new(&img)
+            .with_origin(Point { x: 0, y: self.top })
+            .isoline_fragments(self.thresholds);
+        for (fragments, level) in self.fragments.iter_mut().zip(levels) {
+            fragments.extend(level);
+        }
+
+        // The last row of this tile is the first row of the next
+        let mut data = img.into_data();
+        data.drain(..data.len() - self.width as usize);
+        self.tile = data;
+        self.top += rows - 1;
+    }
+
+    /// Paths cut where one tile meets the next are joined by the cells and edges at their
+    /// ends, exactly as for the chunks of a whole image, as a threshold equal to a sample on
+    /// the shared row can put several ends on the same point
+    fn finish(mut self) -> Vec<IsolineLayer> {
+        // Rows left over after the last full tile, unless it's only the shared row
+        if self.tile.len() > self.width as usize || self.top == 0 {
+            self.contour_tile();
+        }
+
+        self.thresholds
+            .iter()
+            .zip(self.fragments)
+            .map(|(threshold, fragments)| IsolineLayer {
+                threshold: *threshold,
+                paths: stitch_fragments(fragments),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytes_to_image;
+    use std::io::Cursor;
+    use tiff::encoder::{colortype, TiffEncoder};
+    use tiff::tags::{PhotometricInterpretation, SampleFormat, Tag};
+
+    fn samples(width: u32, height: u32) -> Vec<f32> {
+        (0..width * height)
+            .map(|i| {
+                let (x, y) = ((i % width) as f32, (i / width) as f32);
+                if i % 31 == 9 {
+                    -9999.0
+                } else {
+                    (x * 0.5).sin() * (y * 0.35).cos() * 5.0 + y * 0.04
+                }
+            })
+            .collect()
+    }
+
+    fn tiff(width: u32, height: u32, rows_per_strip: u32) -> Cursor<Vec<u8>> {
+        tiff_of(&samples(width, height), width, height, rows_per_strip)
+    }
+
+    fn tiff_of(data: &[f32], width: u32, height: u32, rows_per_strip: u32) -> Cursor<Vec<u8>> {
+        let mut buffer = Cursor::new(Vec::new());
+        let mut encoder = TiffEncoder::new(&mut buffer).unwrap();
+        let mut tiff_image = encoder
+            .new_image::<colortype::Gray32Float>(width, height)
+            .unwrap();
+        tiff_image
+            .encoder()
+            .write_tag(Tag::GdalNodata, "-9999")
+            .unwrap();
+        tiff_image.rows_per_strip(rows_per_strip).unwrap();
+        tiff_image.write_data(data).unwrap();
+        buffer.set_position(0);
+        buffer
+    }
+
+    // The encoder only writes strips, so the tiles are laid out by hand, padded to full size
+    // at the right and bottom edges
+    fn tiled_tiff(width: u32, height: u32, tile_size: u32) -> Cursor<Vec<u8>> {
+        let data = samples(width, height);
+        let mut buffer = Cursor::new(Vec::new());
+        let mut encoder = TiffEncoder::new(&mut buffer).unwrap();
+        let mut directory = encoder.new_directory().unwrap();
+        let (mut offsets, mut byte_counts) = (Vec::new(), Vec::new());
+        for top in (0..height).step_by(tile_size as usize) {
+            for left in (0..width).step_by(tile_size as usize) {
+                let tile: Vec<f32> = (top..top + tile_size)
+                    .flat_map(|y| (left..left + tile_size).map(move |x| (x, y)))
+                    .map(|(x, y)| {
+                        if x < width && y < height {
+                            data[(y * width + x) as usize]
+                        } else {
+                            0.0
+                        }
+                    })
+                    .collect();
+                offsets.push(directory.write_data(&tile[..]).unwrap() as u32);
+                byte_counts.push(tile.len() as u32 * 4);
+            }
+        }
+        directory.write_tag(Tag::ImageWidth, width).unwrap();
+        directory.write_tag(Tag::ImageLength, height).unwrap();
+        directory.write_tag(Tag::BitsPerSample, 32u16).unwrap();
+        directory.write_tag(Tag::Compression, 1u16).unwrap();
+        directory
+            .write_tag(
+                Tag::PhotometricInterpretation,
+                PhotometricInterpretation::BlackIsZero.to_u16(),
+            )
+            .unwrap();
+        directory.write_tag(Tag::SamplesPerPixel, 1u16).unwrap();
+        directory
+            .write_tag(Tag::SampleFormat, SampleFormat::IEEEFP.to_u16())
+            .unwrap();
+        directory.write_tag(Tag::TileWidth, tile_size).unwrap();
+        directory.write_tag(Tag::TileLength, tile_size).unwrap();
+        directory.write_tag(Tag::TileOffsets, &offsets[..]).unwrap();
+        directory
+            .write_tag(Tag::TileByteCounts, &byte_counts[..])
+            .unwrap();
+        directory.write_tag(Tag::GdalNodata, "-9999").unwrap();
+        directory.finish().unwrap();
+        buffer.set_position(0);
+        buffer
+    }
+
+    #[test]
+    fn test_matches_whole_image() {
+        let (width, height) = (23, 37);
+        let thresholds = [-2.0, 0.5, 3.0];
+        let image = bytes_to_image(tiff(width, height, 5).get_ref()).unwrap();
+        let expected = MarchingSquares::new(&image).isolines(&thresholds);
+        assert!(expected.iter().all(|layer| layer.paths.len() > 2));
+
+        let row_bytes = width as usize * 4;
+        for (rows_per_strip, tile_rows) in [(5, 2), (5, 3), (5, 7), (1, 4), (37, 10), (4, 37)] {
+            let tiled = tiled_isolines_from_tiff(
+                tiff(width, height, rows_per_strip),
+                &thresholds,
+                tile_rows * row_bytes,
+            )
+            .unwrap();
+            assert_eq!((tiled.width, tiled.height), (width, height));
+            assert_eq!(
+                tiled.layers, expected,
+                "{} rows per strip and {} rows per tile",
+                rows_per_strip, tile_rows
+            );
+        }
+    }
+
+    #[test]
+    fn test_thresholds_on_samples() {
+        // Lines through a sample on a seam row meet there from several directions, so they
+        // have to be joined by the edges they cross rather than by the point alone
+        let (width, height) = (23, 37);
+        let data: Vec<f32> = samples(width, height)
+            .into_iter()
+            .map(|sample| sample.round())
+            .collect();
+        let thresholds = [-2.0, 0.0, 1.0, 3.0];
+        let image = bytes_to_image(tiff_of(&data, width, height, 1).get_ref()).unwrap();
+        let expected = MarchingSquares::new(&image).isolines(&thresholds);
+
+        let row_bytes = width as usize * 4;
+        for tile_rows in 2..=9 {
+            let tiled = tiled_isolines_from_tiff(
+                tiff_of(&data, width, height, 1),
+                &thresholds,
+                tile_rows * row_bytes,
+            )
+            .unwrap();
+            assert_eq!(tiled.layers, expected, "{} rows per tile", tile_rows);
+        }
+    }
+
+    #[test]
+    fn test_tiled_tiff() {
+        let (width, height) = (41, 37);
+        let thresholds = [-2.0, 0.5, 3.0];
+        let image = bytes_to_image(tiff(width, height, 5).get_ref()).unwrap();
+        let expected = MarchingSquares::new(&image).isolines(&thresholds);
+
+        let row_bytes = width as usize * 4;
+        for tile_rows in [2, 7, 16, 37] {
+            let tiled = tiled_isolines_from_tiff(
+                tiled_tiff(width, height, 16),
+                &thresholds,
+                tile_rows * row_bytes,
+            )
+            .unwrap();
+            assert_eq!((tiled.width, tiled.height), (width, height));
+            assert_eq!(tiled.layers, expected, "{} rows per tile", tile_rows);
+        }
+    }
+
+    #[test]
+    fn test_budget() {
+        assert!(matches!(
+            tiled_isolines_from_tiff(tiff(10, 10, 2), &[1.0], 79),
+            Err(ContourError::InvalidData(_))
+        ));
+        assert!(tiled_isolines_from_tiff(tiff(10, 10, 2), &[1.0], 80).is_ok());
+    }
+}
@@ -0,0 +1,502 @@
+use super::marching_squares::{IsolineLayer, Path};
+use super::simplify::{Corner, Simplification, MIN_RING_POINTS};
+use super::util::*;
+use std::collections::{BinaryHeap, HashMap};
+
+/// Simplify every path of every layer together, never removing a point if that would make
+/// a line cross itself or any other line, at any level
+///
+/// Points are removed one at a time across all the paths, cheapest first, where the cost
+/// is the area of the triangle a point makes with its neighbours for Visvalingam-Whyatt, and
+/// the distance from the line between them of the farthest point that line replaces, this
+/// one or any removed before, for Douglas-Peucker. A removal is only made if no segment
+/// crosses the new line and no point is inside the triangle it cuts off, and points kept
+/// that way are tried again once others have gone, until nothing more can be removed. Open
+/// paths keep their end points, and closed rings keep their first point and three corners.
+pub fn simplify_preserving_topology(
+    layers: &[IsolineLayer],
+    simplification: &Simplification,
+) -> Vec<IsolineLayer> {
+    let paths: Vec<&Path> = layers.iter().flat_map(|layer| &layer.paths).collect();
+    let mut simplifier = Simplifier::new(&paths);
+
+    let mut heap: BinaryHeap<Corner> = BinaryHeap::new();
+    for (path, points) in paths.iter().enumerate() {
+        for index in 1..points.points.len().saturating_sub(1) {
+            heap.push(simplifier.candidate(simplification, path, index));
+        }
+    }
+
+    let mut blocked = Vec::new();
+    loop {
+        let mut removed = false;
+        while let Some(candidate) = heap.pop() {
+            if !simplifier.is_current(&candidate) {
+                continue;
+            }
+            if !simplification.removes(candidate.cost) {
+                break;
+            }
+            if !simplifier.can_remove(&candidate) {
+                blocked.push(candidate);
+                continue;
+            }
+
+            let (path, index) = (candidate.path, candidate.index);
+            simplifier.remove(path, index);
+            removed = true;
+            for neighbour in [candidate.previous, candidate.next] {
+                if simplifier.is_interior(path, neighbour) {
+                    heap.push(simplifier.candidate(simplification, path, neighbour));
+                }
+            }
+        }
+
+        // Whatever was in the way of a blocked point may have been simplified away since
+        if !removed {
+            break;
+        }
+        heap.extend(blocked.drain(..));
+    }
+
+    let mut simplified = simplifier.into_paths().into_iter();
+    layers
+        .iter()
+        .map(|layer| IsolineLayer {
+            threshold: layer.threshold,
+            paths: simplified.by_ref().take(layer.paths.len()).collect(),
+        })
+        .collect()
+}
+
+/// Paths with points being removed, linked so each live point knows its live neighbours
+struct Simplifier<'a> {
+    paths: &'a [&'a Path],
+    previous: Vec<Vec<usize>>,
+    next: Vec<Vec<usize>>,
+    kept: Vec<Vec<bool>>,
+    remaining: Vec<usize>,
+    grid: SegmentGrid,
+}
+
+impl<'a> Simplifier<'a> {
+    fn new(paths: &'a [&'a Path]) -> Simplifier<'a> {
+        let mut grid = SegmentGrid::new(paths);
+        for (path, points) in paths.iter().enumerate() {
+            for (start, pair) in points.points.windows(2).enumerate() {
+                grid.insert((path, start), &pair[0], &pair[1]);
+            }
+        }
+
+        Simplifier {
+            paths,
+            previous: paths
+                .iter()
+                .map(|path| {
+                    (0..path.points.len())
+                        .map(|i| i.saturating_sub(1))
+                        .collect()
+                })
+                .collect(),
+            next: paths
+                .iter()
+                .map(|path| (1..=path.points.len()).collect())
+                .collect(),
+            kept: paths
+                .iter()
+                .map(|path| vec![true; path.points.len()])
+                .collect(),
+            remaining: paths.iter().map(|path| path.points.len()).collect(),
+            grid,
+        }
+    }
+
+    fn point(&self, path: usize, index: usize) -> &Point<f32> {
+        &self.paths[path].points[index]
+    }
+
+    fn is_interior(&self, path: usize, index: usize) -> bool {
+        index != 0 && index != self.paths[path].points.len() - 1
+    }
+
+    fn candidate(&self, simplification: &Simplification, path: usize, index: usize) -> Corner {
+        let (previous, next) = (self.previous[path][index], self.next[path][index]);
+        Corner {
+            cost: simplification.cost(
+                self.point(path, previous),
+                self.point(path, index),
+                self.point(path, next),
+                &self.paths[path].points[previous + 1..next],
+            ),
+            path,
+            index,
+            previous,
+            next,
+        }
+    }
+
+    fn is_current(&self, candidate: &Corner) -> bool {
+        let (path, index) = (candidate.path, candidate.index);
+        self.kept[path][index]
+            && self.previous[path][index] == candidate.previous
+            && self.next[path][index] == candidate.next
+    }
+
+    /// Whether the line from the previous point to the next can replace the point without
+    /// crossing or passing over any other part of any path
+    fn can_remove(&self, candidate: &Corner) -> bool {
+        let path = candidate.path;
+        if self.paths[path].circular && self.remaining[path] <= MIN_RING_POINTS {
+            return false;
+        }
+
+        let a = self.point(path, candidate.previous);
+        let b = self.point(path, candidate.index);
+        let c = self.point(path, candidate.next);
+        let (min, max) = bounds(&[*a, *b, *c]);
+        self.grid.segments_within(&min, &max).all(|(other, start)| {
+            // The two segments being replaced
+            if other == path && (start == candidate.previous || start == candidate.index) {
+                return true;
+            }
+            let start_point = self.point(other, start);
+            let end_point = self.point(other, self.next[other][start]);
+
+            !crosses(start_point, end_point, a, c)
+                && !inside_triangle(start_point, a, b, c)
+                && !inside_triangle(end_point, a, b, c)
+        })
+    }
+
+    fn remove(&mut self, path: usize, index: usize) {
+        let (previous, next) = (self.previous[path][index], self.next[path][index]);
+        let (a, b, c) = (
+            *self.point(path, previous),
+            *self.point(path, index),
+            *self.point(path, next),
+        );
+        self.grid.remove((path, previous), &a, &b);
+        self.grid.remove((path, index), &b, &c);
+        self.grid.insert((path, previous), &a, &c);
+
+        self.kept[path][index] = false;
+        self.remaining[path] -= 1;
+        self.next[path][previous] = next;
+        self.previous[path][next] = previous;
+    }
+
+    fn into_paths(self) -> Vec<Path> {
+        self.paths
+            .iter()
+            .zip(&self.kept)
+            .map(|(path, kept)| Path {
+                points: path
+                    .points
+                    .iter()
+                    .zip(kept)
+                    .filter(|(_, kept)| **kept)
+                    .map(|(point, _)| *point)
+                    .collect(),
+                circular: path.circular,
+            })
+            .collect()
+    }
+}
+
+/// The segments of every path bucketed by the square cells of a grid they overlap, each
+/// named by its path and the index of its first point
+struct SegmentGrid {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<(usize, usize)>>,
+}
+
+impl SegmentGrid {
+    // Roughly one of the original segments to a cell
+    fn new(paths: &[&Path]) -> SegmentGrid {
+        let points: Vec<Point<f32>> = paths
+            .iter()
+            .flat_map(|path| path.points.iter().copied())
+            .collect();
+        let (min, max) = bounds(&points);
+        let area = (max.x - min.x).max(1.0) * (max.y - min.y).max(1.0);
+        SegmentGrid {
+            cell_size: (area / points.len().max(1) as f32).sqrt().max(f32::EPSILON),
+            cells: HashMap::new(),
+        }
+    }
+
+    fn cell(&self, point: &Point<f32>) -> (i32, i32) {
+        (
+            (point.x / self.cell_size).floor() as i32,
+            (point.y / self.cell_size).floor() as i32,
+        )
+    }
+
+    fn cells_within(&self, min: &Point<f32>, max: &Point<f32>) -> Vec<(i32, i32)> {
+        let (min_x, min_y) = self.cell(min);
+        let (max_x, max_y) = self.cell(max);
+        (min_y..=max_y)
+            .flat_map(|y| (min_x..=max_x).map(move |x| (x, y)))
+            .collect()
+    }
+
+    fn insert(&mut self, segment: (usize, usize), start: &Point<f32>, end: &Point<f32>) {
+        let (min, max) = bounds(&[*start, *end]);
+        for cell in self.cells_within(&min, &max) {
+            self.cells.entry(cell).or_default().push(segment);
+        }
+    }
+
+    fn remove(&mut self, segment: (usize, usize), start: &Point<f32>, end: &Point<f32>) {
+        let (min, max) = bounds(&[*start, *end]);
+        for cell in self.cells_within(&min, &max) {
+            if let Some(segments) = self.cells.get_mut(&cell) {
+                segments.retain(|other| *other != segment);
+            }
+        }
+    }
+
+    /// Every segment in a cell overlapping the box, some more than once
+    fn segments_within<'g>(
+        &'g self,
+        min: &Point<f32>,
+        max: &Point<f32>,
+    ) -> impl Iterator<Item = (usize, usize)> + 'g {
+        self.cells_within(min, max)
+            .into_iter()
+            .filter_map(move |cell| self.cells.get(&cell))
+            .flatten()
+            .copied()
+    }
+}
+
+fn bounds(points: &[Point<f32>]) -> (Point<f32>, Point<f32>) {
+    let mut min = Point {
+        x: f32::INFINITY,
+        y: f32::INFINITY,
+    };
+    let mut max = Point {
+        x: f32::NEG_INFINITY,
+        y: f32::NEG_INFINITY,
+    };
+    for point in points {
+        min.x = min.x.min(point.x);
+        min.y = min.y.min(point.y);
+        max.x = max.x.max(point.x);
+        max.y = max.y.max(point.y);
+    }
+    (min, max)
+}
+
+/// Twice the signed area of the triangle, positive if it turns one way and negative the other
+fn orientation(a: &Point<f32>, b: &Point<f32>, c: &Point<f32>) -> f32 {
+    (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x)
+}
+
+/// Whether two segments meet anywhere other than at an end they share, including where an
+/// end of one touches the middle of the other
+fn crosses(p: &Point<f32>, q: &Point<f32>, a: &Point<f32>, c: &Point<f32>) -> bool {
+    let touches = |point: &Point<f32>, start: &Point<f32>, end: &Point<f32>| {
+        point != start
+            && point != end
+            && orientation(start, end, point) == 0.0
+            && point.x >= start.x.min(end.x)
+            && point.x <= start.x.max(end.x)
+            && point.y >= start.y.min(end.y)
+            && point.y <= start.y.max(end.y)
+    };
+    if touches(p, a, c) || touches(q, a, c) || touches(a, p, q) || touches(c, p, q) {
+        return true;
+    }
+
+    let (pq_a, pq_c) = (orientation(p, q, a), orientation(p, q, c));
+    let (ac_p, ac_q) = (orientation(a, c, p), orientation(a, c, q));
+    pq_a * pq_c < 0.0 && ac_p * ac_q < 0.0
+}
+
+fn inside_triangle(point: &Point<f32>, a: &Point<f32>, b: &Point<f32>, c: &Point<f32>) -> bool {
+    let sides = [
+        orientation(a, b, point),
+        orientation(b, c, point),
+        orientation(c, a, point),
+    ];
+    sides.iter().all(|side| *side > 0.0) || sides.iter().all(|side| *side < 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::marching_squares::path;
+
+    fn layer(threshold: f32, paths: Vec<Path>) -> IsolineLayer {
+        IsolineLayer { threshold, paths }
+    }
+
+    #[test]
+    fn test_crosses() {
+        let point = |x, y| Point { x, y };
+        let (a, c) = (point(0.0, 0.0), point(2.0, 0.0));
+        assert!(crosses(&point(1.0, -1.0), &point(1.0, 1.0), &a, &c));
+        assert!(!crosses(&point(1.0, 0.5), &point(1.0, 1.0), &a, &c));
+        // Touching the middle counts, sharing an end doesn't
+        assert!(crosses(&point(1.0, 0.0), &point(1.0, 1.0), &a, &c));
+        assert!(!crosses(&c, &point(3.0, 1.0), &a, &c));
+    }
+
+    #[test]
+    fn test_keeps_lines_apart() {
+        // A spike on the lower line reaching up into a small bump on the upper line
+        let lower = path(
+            &[(0.0, 0.0), (1.0, 0.0), (2.5, 1.2), (4.0, 0.0), (5.0, 0.0)],
+            false,
+        );
+        let upper = path(&[(0.0, 1.0), (2.5, 1.5), (5.0, 1.0)], false);
+        let layers = vec![
+            layer(1.0, vec![lower.clone()]),
+            layer(2.0, vec![upper.clone()]),
+        ];
+        let crossing = |lower: &Path, upper: &Path| {
+            lower.points.windows(2).any(|pair| {
+                upper
+                    .points
+                    .windows(2)
+                    .any(|other| crosses(&pair[0], &pair[1], &other[0], &other[1]))
+            })
+        };
+        assert!(!crossing(&lower, &upper));
+
+        // On their own the bump is flattened but the spike stays, poking through it
+        let simplification = Simplification::VisvalingamWhyatt { min_area: 1.5 };
+        let (alone_lower, alone_upper) =
+            (simplification.apply(&lower), simplification.apply(&upper));
+        assert_eq!(alone_upper.points.len(), 2);
+        assert!(crossing(&alone_lower, &alone_upper));
+
+        let simplified = simplify_preserving_topology(&layers, &simplification);
+        assert_eq!(simplified[0].threshold, 1.0);
+        assert_eq!(simplified[1].threshold, 2.0);
+        let (lower, upper) = (&simplified[0].paths[0], &simplified[1].paths[0]);
+        assert_eq!(lower, &alone_lower);
+        assert_eq!(upper, &layers[1].paths[0]);
+        assert!(!crossing(lower, upper));
+    }
+
+    #[test]
+    fn test_keeps_rings_around_points() {
+        // A small ring inside the corner the outer ring would cut
+        let outer = path(
+            &[
+                (0.0, 0.0),
+                (4.0, 0.0),
+                (4.0, 4.0),
+                (0.0, 4.0),
+                (0.0, 3.0),
+                (0.0, 0.0),
+            ],
+            true,
+        );
+        let inner = path(
+            &[(0.5, 3.0), (0.7, 3.0), (0.7, 3.2), (0.5, 3.2), (0.5, 3.0)],
+            true,
+        );
+        let layers = vec![
+            layer(1.0, vec![outer.clone()]),
+            layer(2.0, vec![inner.clone()]),
+        ];
+        let simplification = Simplification::DouglasPeucker { tolerance: 5.0 };
+        let simplified = simplify_preserving_topology(&layers, &simplification);
+
+        // The corner at (0, 4) stays, while the point along the edge at (0, 3) can go
+        let outer = &simplified[0].paths[0];
+        assert!(outer.circular);
+        assert!(outer.points.contains(&Point { x: 0.0, y: 4.0 }));
+        assert!(!outer.points.contains(&Point { x: 0.0, y: 3.0 }));
+        assert_eq!(outer.points.first(), outer.points.last());
+
+        // The inner ring is still cut down to a triangle of its own
+        let inner = &simplified[1].paths[0];
+        assert_eq!(inner.points.len(), MIN_RING_POINTS);
+        assert_eq!(inner.points.first(), inner.points.last());
+    }
+
+    #[test]
+    fn test_matches_unconstrained_when_apart() {
+        let lines = vec![
+            layer(
+                1.0,
+                vec![path(
+                    &[(0.0, 0.0), (1.0, 0.05), (2.0, 0.0), (3.0, 0.05)],
+                    false,
+                )],
+            ),
+            layer(
+                2.0,
+                vec![path(
+                    &[(0.0, 5.0), (1.0, 5.05), (2.0, 5.0), (3.0, 5.05)],
+                    false,
+                )],
+            ),
+        ];
+        for simplification in [
+            Simplification::VisvalingamWhyatt { min_area: 0.5 },
+            Simplification::DouglasPeucker { tolerance: 0.5 },
+        ] {
+            let simplified = simplify_preserving_topology(&lines, &simplification);
+            for (layer, original) in simplified.iter().zip(&lines) {
+                assert_eq!(layer.paths, vec![simplification.apply(&original.paths[0])]);
+                assert_eq!(layer.paths[0].points.len(), 2);
+            }
+        }
+    }
+
+    #[test]
+    fn test_douglas_peucker_tolerance() {
+        // Judging each point only against its current neighbours would leave (1, -0.3) 0.64
+        // from the simplified line
+        let line = path(
+            &[
+                (0.0, -0.1),
+                (1.0, -0.3),
+                (2.0, 0.4),
+                (3.0, 0.6),
+                (4.0, 0.5),
+                (5.0, 0.0),
+            ],
+            false,
+        );
+        let tolerance = 0.5;
+        let simplified = simplify_preserving_topology(
+            &[layer(1.0, vec![line.clone()])],
+            &Simplification::DouglasPeucker { tolerance },
+        );
+        let kept = &simplified[0].paths[0].points;
+        assert!(kept.len() < line.points.len());
+        for point in &line.points {
+            let segment = kept
+                .windows(2)
+                .find(|pair| pair[0].x <= point.x && point.x <= pair[1].x)
+                .unwrap();
+            let (dx, dy) = (segment[1].x - segment[0].x, segment[1].y - segment[0].y);
+            let distance = (dy * (point.x - segment[0].x) - dx * (point.y - segment[0].y)).abs()
+                / dx.hypot(dy);
+            assert!(distance <= tolerance, "{:?} is {} away", point, distance);
+        }
+    }
+
+    #[test]
+    fn test_retries_blocked_points() {
+        // The bump on the upper line is cheaper to remove, but the spike of the lower line is
+        // inside it until the spike is removed too
+        let upper = path(&[(0.0, 0.0), (2.0, 0.3), (4.0, 0.0)], false);
+        let lower = path(&[(1.5, -5.0), (2.0, 0.1), (2.5, -5.0)], false);
+        let layers = vec![
+            layer(1.0, vec![upper.clone()]),
+            layer(2.0, vec![lower.clone()]),
+        ];
+        let simplification = Simplification::VisvalingamWhyatt { min_area: 3.0 };
+        let simplified = simplify_preserving_topology(&layers, &simplification);
+        assert_eq!(simplified[0].paths, vec![simplification.apply(&upper)]);
+        assert_eq!(simplified[1].paths, vec![simplification.apply(&lower)]);
+        assert_eq!(simplified[0].paths[0].points.len(), 2);
+    }
+}
@@ -0,0 +1,134 @@
+use super::marching_squares::map_parallel;
+use super::util::*;
+
+/// The image with `factor - 1` new samples between each pair of neighbours, interpolated
+/// with a Catmull-Rom spline along the rows and then down the columns
+///
+/// Sample `(x, y)` of the original is sample `(x * factor, y * factor)` of the result, with
+/// exactly the same value, so contours of the result still pass through the data. Anything
+/// between missing samples is missing too, and spline neighbours that are off the image or
+/// missing are extrapolated linearly from the two samples they'd sit next to.
+pub(crate) fn upsample(img: &Image<f32>, factor: u32) -> Image<f32> {
+    let width = img.width.saturating_sub(1) * factor + 1;
+    let height = img.height.saturating_sub(1) * factor + 1;
+
+    let rows = map_parallel((0..img.height).collect(), |y| {
+        (0..width)
+            .map(|x| {
+                spline_at(x, factor, |i| {
+                    i.and_then(|i| img.get_val(&Point { x: i, y }))
+                })
+            })
+            .collect::<Vec<f32>>()
+    });
+    let rows = Image::new(rows.concat(), width, img.height);
+
+    let data = map_parallel((0..height).collect(), |y| {
+        (0..width)
+            .map(|x| {
+                spline_at(y, factor, |i| {
+                    i.and_then(|i| rows.get_val(&Point { x, y: i }))
+                })
+            })
+            .collect::<Vec<f32>>()
+    });
+    Image::new(data.concat(), width, height)
+}
+
+/// Value at `position` on the upsampled axis, from the samples of the original axis
+/// returned by `sample`, which is given `None` for positions before the start
+fn spline_at<F: Fn(Option<u32>) -> Option<f32>>(position: u32, factor: u32, sample: F) -> f32 {
+    let (i, step) = (position / factor, position % factor);
+    let at = |offset: i32| sample(i.checked_add_signed(offset));
+    if step == 0 {
+        return at(0).unwrap_or(f32::NAN);
+    }
+
+    match (at(0), at(1)) {
+        (Some(p1), Some(p2)) => {
+            let p0 = at(-1).unwrap_or(2.0 * p1 - p2);
+            let p3 = at(2).unwrap_or(2.0 * p2 - p1);
+            catmull_rom([p0, p1, p2, p3], step as f32 / factor as f32)
+        }
+        _ => f32::NAN,
+    }
+}
+
+/// The spline through `p[1]` at `t = 0` and `p[2]` at `t = 1`
+///
+/// Clamped to the range of `p[1]` and `p[2]`, as the spline overshoots next to a steep
+/// step and would otherwise add contours around levels the data never reaches.
+fn catmull_rom(p: [f32; 4], t: f32) -> f32 {
+    let (t2, t3) = (t * t, t * t * t);
+    let value = 0.5
+        * (p[0] * (-t3 + 2.0 * t2 - t)
+            + p[1] * (3.0 * t3 - 5.0 * t2 + 2.0)
+            + p[2] * (-3.0 * t3 + 4.0 * t2 + t)
+            + p[3] * (t3 - t2));
+    value.clamp(p[1].min(p[2]), p[1].max(p[2]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catmull_rom() {
+        let p = [1.0, 2.0, 4.0, 8.0];
+        assert_eq!(catmull_rom(p, 0.0), 2.0);
+        assert_eq!(catmull_rom(p, 1.0), 4.0);
+        assert_eq!(catmull_rom(p, 0.5), 2.8125);
+
+        // Straight lines stay straight
+        assert_eq!(catmull_rom([0.0, 1.0, 2.0, 3.0], 0.25), 1.25);
+
+        // A plateau stays flat rather than bulging up to 1.125
+        assert_eq!(catmull_rom([0.0, 1.0, 1.0, 0.0], 0.5), 1.0);
+        assert_eq!(catmull_rom([1.0, 0.0, 0.0, 1.0], 0.5), 0.0);
+    }
+
+    #[test]
+    fn test_upsample() {
+        let data = vec![
+            0.0, 1.0, 4.0, //
+            1.0, 2.0, 5.0, //
+            4.0, 5.0, 8.0,
+        ];
+        let img = Image::new(data, 3, 3);
+        let fine = upsample(&img, 4);
+        assert_eq!((fine.width, fine.height), (9, 9));
+
+        // The original samples are kept exactly
+        for y in 0..3 {
+            for x in 0..3 {
+                assert_eq!(
+                    fine.get_val(&Point { x: x * 4, y: y * 4 }),
+                    img.get_val(&Point { x, y })
+                );
+            }
+        }
+
+        // Halfway along the top row, bending with the samples either side rather than
+        // splitting the difference
+        assert_eq!(fine.get_val(&Point { x: 2, y: 0 }), Some(0.375));
+        assert_eq!(fine.get_val(&Point { x: 6, y: 0 }), Some(2.375));
+        assert_eq!(fine.get_val(&Point { x: 6, y: 4 }), Some(3.375));
+    }
+
+    #[test]
+    fn test_missing() {
+        let data = vec![0.0, 1.0, f32::NAN, 3.0, 4.0, 5.0];
+        let img = Image::new(data, 3, 2);
+        let fine = upsample(&img, 2);
+        assert_eq!((fine.width, fine.height), (5, 3));
+
+        // Nothing between a missing sample and its neighbours
+        assert_eq!(fine.get_val(&Point { x: 3, y: 0 }), None);
+        assert_eq!(fine.get_val(&Point { x: 4, y: 1 }), None);
+        assert_eq!(fine.get_val(&Point { x: 4, y: 2 }), Some(5.0));
+
+        // Spline neighbours past the missing sample are extrapolated instead
+        assert_eq!(fine.get_val(&Point { x: 1, y: 0 }), Some(0.5));
+        assert_eq!(fine.get_val(&Point { x: 1, y: 1 }), Some(2.0));
+    }
+}
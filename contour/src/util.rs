@@ -1,3 +1,5 @@
+use super::error::{ContourError, ContourResult};
+use super::geotiff::GeoTransform;
 use std::hash::{Hash, Hasher};
 use std::iter::FromIterator;
 use std::ops::Add;
@@ -11,10 +13,10 @@ pub struct Point<T> {
 impl<T: Add + Copy> Add<Point<T>> for Point<T> {
     type Output = Point<T::Output>;
     fn add(self, other: Point<T>) -> Point<T::Output> {
-        return Point {
+        Point {
             x: self.x + other.x,
             y: self.y + other.y,
-        };
+        }
     }
 }
 impl<T: Add + Copy> Add<&Point<T>> for Point<T> {
@@ -110,28 +112,96 @@ impl Hash for Point<u32> {
 impl Eq for Point<u32> {}
 impl Eq for Point<f32> {}
 
+#[derive(Clone, Debug)]
 pub struct Image<T> {
     data: Vec<T>,
     pub width: u32,
     pub height: u32,
+    pub geotransform: Option<GeoTransform>,
+    nodata: Option<T>,
+    mask: Option<Vec<bool>>,
 }
 
-impl<T: Clone> Image<T> {
+impl<T: Clone + PartialEq> Image<T> {
     pub fn new(data: Vec<T>, width: u32, height: u32) -> Image<T> {
         Image {
             data,
             width,
             height,
+            geotransform: None,
+            nodata: None,
+            mask: None,
         }
     }
 
+    /// Place the image on a map, contours are then drawn in map coordinates
+    pub fn with_geotransform(mut self, geotransform: GeoTransform) -> Image<T> {
+        self.geotransform = Some(geotransform);
+        self
+    }
+
+    /// Treat every sample equal to `nodata` as missing
+    pub fn with_nodata(mut self, nodata: T) -> Image<T> {
+        self.nodata = Some(nodata);
+        self
+    }
+
+    /// Only samples whose entry in `mask` is true are valid, the mask is laid out like the data
+    pub fn with_mask(mut self, mask: Vec<bool>) -> ContourResult<Image<T>> {
+        if mask.len() != self.data.len() {
+            return Err(ContourError::DimensionMismatch {
+                width: self.width,
+                height: self.height,
+                samples: mask.len(),
+            });
+        }
+        self.mask = Some(mask);
+        Ok(self)
+    }
+
+    /// Whether `pt` is a sample of the image, whether or not it's missing
+    pub fn contains(&self, pt: &Point<u32>) -> bool {
+        pt.x < self.width && pt.y < self.height
+    }
+
+    /// The sample at `pt`, or `None` if it is off the image, masked out, nodata or NaN
     pub fn get_val(&self, pt: &Point<u32>) -> Option<T> {
-        if pt.x >= self.width || pt.y >= self.height {
+        if !self.contains(pt) {
             return None;
         }
 
-        Some(self.data[point_to_index(pt, self.width)].clone())
+        let index = point_to_index(pt, self.width);
+        if let Some(mask) = &self.mask {
+            if !mask[index] {
+                return None;
+            }
+        }
+
+        let val = &self.data[index];
+        if self.nodata.as_ref() == Some(val) || is_nan(val) {
+            return None;
+        }
+
+        Some(val.clone())
+    }
+
+    /// Give back the samples, in row order
+    pub fn into_data(self) -> Vec<T> {
+        self.data
     }
+
+    /// Every sample that isn't missing, in row order
+    pub fn values(&self) -> impl Iterator<Item = T> + '_ {
+        (0..self.height)
+            .flat_map(move |y| (0..self.width).map(move |x| Point { x, y }))
+            .filter_map(move |pt| self.get_val(&pt))
+    }
+}
+
+// NaN is the only value that isn't equal to itself
+#[allow(clippy::eq_op)]
+fn is_nan<T: PartialEq>(val: &T) -> bool {
+    val != val
 }
 
 fn point_to_index(point: &Point<u32>, width: u32) -> usize {
@@ -148,4 +218,38 @@ mod tests {
         assert_eq!(point_to_index(&pt, 1), 2);
         assert_eq!(point_to_index(&pt, 3), 6);
     }
+
+    #[test]
+    fn test_get_val() {
+        let data = vec![1.0, -32768.0, f32::NAN, 4.0];
+
+        let img = Image::new(data.clone(), 2, 2);
+        assert_eq!(img.get_val(&Point { x: 0, y: 0 }), Some(1.0));
+        assert_eq!(img.get_val(&Point { x: 1, y: 0 }), Some(-32768.0));
+        assert_eq!(img.get_val(&Point { x: 0, y: 1 }), None);
+        assert_eq!(img.get_val(&Point { x: 2, y: 1 }), None);
+
+        let img = Image::new(data.clone(), 2, 2).with_nodata(-32768.0);
+        assert_eq!(img.get_val(&Point { x: 1, y: 0 }), None);
+        assert_eq!(img.get_val(&Point { x: 1, y: 1 }), Some(4.0));
+
+        let img = Image::new(data.clone(), 2, 2)
+            .with_mask(vec![false, true, true, true])
+            .unwrap();
+        assert_eq!(img.get_val(&Point { x: 0, y: 0 }), None);
+        assert_eq!(img.get_val(&Point { x: 1, y: 0 }), Some(-32768.0));
+
+        // A mask has to cover every sample
+        assert!(matches!(
+            Image::new(data, 2, 2).with_mask(vec![true, true, true]),
+            Err(ContourError::DimensionMismatch { samples: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn test_values() {
+        let data = vec![1.0, -32768.0, f32::NAN, 4.0];
+        let img = Image::new(data, 2, 2).with_nodata(-32768.0);
+        assert_eq!(img.values().collect::<Vec<f32>>(), vec![1.0, 4.0]);
+    }
 }